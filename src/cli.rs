@@ -0,0 +1,387 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Serialize, Serializer};
+use thousands::Separable;
+
+use crate::core::export::{csv_field, export, ExportFormat};
+use crate::core::scanner::{scan_tree, CancelToken, ScanOptions, ScanProgress, ScanResult, SizeMode, TreeNode};
+
+const USAGE: &str = "\
+Usage: mac-space-explorer scan <path> [options]
+
+Scans <path> without opening a window and prints directory totals and the
+largest files.
+
+Options:
+  --depth <n>          Directory levels to list below <path> (default 1)
+  --top <n>            Number of largest files to list (default 10)
+  --format <format>    table, json or csv (default table), or ncdu to
+                       export the whole tree for `ncdu -f -`
+  --size <mode>        apparent or disk (default apparent)
+  --threads <n>        Threads walking the tree (default: one per CPU)
+  --exclude <rule>     Path or glob pattern to leave out; may be repeated
+  --ignore-files       Honor .gitignore and .ignore files
+  --measure-excluded   Read excluded directories to report their size
+  --all-filesystems    Descend into other mounted filesystems
+  -h, --help           Print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+    Ncdu,
+}
+
+/// A parsed `scan` invocation.
+#[derive(Debug)]
+pub struct ScanCommand {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub top: usize,
+    pub format: Format,
+    pub size_mode: SizeMode,
+    pub options: ScanOptions,
+}
+
+/// Whether `args`, without the program name, ask for the command line
+/// rather than the GUI.
+pub fn is_cli(args: &[OsString]) -> bool {
+    matches!(args.first().and_then(|arg| arg.to_str()), Some("scan" | "help" | "-h" | "--help"))
+}
+
+/// Runs the command line and returns the process exit code.
+pub fn run(args: &[OsString]) -> i32 {
+    if args.iter().any(|arg| matches!(arg.to_str(), Some("help" | "-h" | "--help"))) {
+        println!("{}", USAGE);
+        return 0;
+    }
+    let command = match parse(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    let progress = Mutex::new(ScanProgress::default());
+    let result = match scan_tree(&command.path, &command.options, &progress, &CancelToken::new()) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+    if !result.errors.is_empty() {
+        eprintln!(
+            "warning: {} items could not be read and are not counted",
+            result.errors.len().separate_with_commas()
+        );
+    }
+
+    let report = Report::new(&result, &command);
+    match command.format {
+        Format::Table => print!("{}", report.table()),
+        Format::Json => match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            }
+        },
+        Format::Csv => print!("{}", report.csv()),
+        Format::Ncdu => {
+            if let Err(e) = export(io::stdout().lock(), ExportFormat::Ncdu, &result.info, &result.tree, &result.errors) {
+                eprintln!("error: {}", e);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+/// Parses the arguments after the program name. Options and their values
+/// must be UTF-8; the path to scan may be any bytes the platform allows.
+pub fn parse(args: &[OsString]) -> Result<ScanCommand, String> {
+    let mut args = args.iter();
+    match args.next().map(|arg| arg.to_string_lossy()).as_deref() {
+        Some("scan") => {}
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+        None => return Err("missing command".to_string()),
+    }
+
+    let mut command = ScanCommand {
+        path: PathBuf::new(),
+        depth: 1,
+        top: 10,
+        format: Format::Table,
+        size_mode: SizeMode::Apparent,
+        options: ScanOptions::default(),
+    };
+    let mut path = None;
+    while let Some(os_arg) = args.next() {
+        let Some(arg) = os_arg.to_str() else {
+            match path {
+                None => path = Some(PathBuf::from(os_arg)),
+                Some(_) => return Err(format!("unexpected argument '{}'", os_arg.to_string_lossy())),
+            }
+            continue;
+        };
+        // Accept both `--depth 2` and `--depth=2`.
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || match inline.clone() {
+            Some(value) => Ok(value),
+            None => {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+                value.to_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("{} expects UTF-8, got '{}'", flag, value.to_string_lossy()))
+            }
+        };
+        match flag {
+            "--depth" => command.depth = number(flag, &value()?)?,
+            "--top" => command.top = number(flag, &value()?)?,
+            "--threads" => command.options.threads = number(flag, &value()?)?.max(1),
+            "--format" => {
+                command.format = match value()?.as_str() {
+                    "table" => Format::Table,
+                    "json" => Format::Json,
+                    "csv" => Format::Csv,
+                    "ncdu" => Format::Ncdu,
+                    other => return Err(format!("unknown format '{}'", other)),
+                }
+            }
+            "--size" => {
+                command.size_mode = match value()?.as_str() {
+                    "apparent" => SizeMode::Apparent,
+                    "disk" | "on-disk" => SizeMode::OnDisk,
+                    other => return Err(format!("unknown size mode '{}'", other)),
+                }
+            }
+            "--exclude" => command.options.exclude.add(&value()?),
+            "--ignore-files" => command.options.exclude.use_ignore_files = true,
+            "--measure-excluded" => command.options.exclude.measure = true,
+            "--all-filesystems" => command.options.one_file_system = false,
+            _ if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+            _ if path.is_none() => path = Some(PathBuf::from(os_arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    command.path = path.ok_or("missing path to scan")?;
+    Ok(command)
+}
+
+fn number(flag: &str, value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("{} expects a number, got '{}'", flag, value))
+}
+
+/// Writes paths as text, replacing bytes that aren't UTF-8, since serde
+/// refuses to write such a path at all.
+fn lossy<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+#[derive(Serialize)]
+struct Report {
+    #[serde(serialize_with = "lossy")]
+    root: PathBuf,
+    scanned_at: String,
+    size_mode: &'static str,
+    #[serde(skip)]
+    mode: SizeMode,
+    size: u64,
+    allocated: u64,
+    files: u64,
+    unreadable: usize,
+    directories: Vec<DirectoryRow>,
+    largest_files: Vec<FileRow>,
+}
+
+#[derive(Serialize)]
+struct DirectoryRow {
+    #[serde(serialize_with = "lossy")]
+    path: PathBuf,
+    depth: usize,
+    size: u64,
+    allocated: u64,
+    files: u64,
+}
+
+#[derive(Serialize)]
+struct FileRow {
+    #[serde(serialize_with = "lossy")]
+    path: PathBuf,
+    size: u64,
+    allocated: u64,
+}
+
+impl Report {
+    fn new(result: &ScanResult, command: &ScanCommand) -> Self {
+        let mode = command.size_mode;
+        let mut directories = Vec::new();
+        let mut stack = vec![(&result.tree, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            directories.push(DirectoryRow {
+                path: node.path.clone(),
+                depth,
+                size: node.size,
+                allocated: node.allocated,
+                files: node.file_count,
+            });
+            if depth < command.depth {
+                let mut children: Vec<&TreeNode> = node.children.iter().filter(|c| c.is_dir).collect();
+                // Biggest first once popped off the stack.
+                children.sort_by_key(|child| child.size_in(mode));
+                stack.extend(children.into_iter().map(|child| (child, depth + 1)));
+            }
+        }
+
+        let largest_files = result.tree.largest_files(command.top, mode)
+            .into_iter()
+            .map(|entry| FileRow { path: entry.path, size: entry.size, allocated: entry.allocated })
+            .collect();
+
+        Self {
+            root: result.info.root.clone(),
+            scanned_at: chrono::DateTime::<chrono::Local>::from(result.info.scanned_at).to_rfc3339(),
+            size_mode: match mode {
+                SizeMode::Apparent => "apparent",
+                SizeMode::OnDisk => "disk",
+            },
+            mode,
+            size: result.tree.size,
+            allocated: result.tree.allocated,
+            files: result.tree.file_count,
+            unreadable: result.errors.len(),
+            directories,
+            largest_files,
+        }
+    }
+
+    fn mode_size(&self, size: u64, allocated: u64) -> u64 {
+        match self.mode {
+            SizeMode::Apparent => size,
+            SizeMode::OnDisk => allocated,
+        }
+    }
+
+    fn table(&self) -> String {
+        let mb = |bytes: u64| format!("{} MB", (bytes / 1024 / 1024).separate_with_commas());
+        let mut out = format!("{:>14}  {:>10}  PATH\n", "SIZE", "FILES");
+        for dir in &self.directories {
+            let name = match dir.depth {
+                0 => dir.path.display().to_string(),
+                _ => dir.path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            };
+            out.push_str(&format!(
+                "{:>14}  {:>10}  {}{}\n",
+                mb(self.mode_size(dir.size, dir.allocated)),
+                dir.files.separate_with_commas(),
+                "  ".repeat(dir.depth),
+                name
+            ));
+        }
+        if !self.largest_files.is_empty() {
+            out.push_str(&format!("\nLargest files ({}):\n", self.size_mode));
+            for file in &self.largest_files {
+                out.push_str(&format!(
+                    "{:>14}  {}\n",
+                    mb(self.mode_size(file.size, file.allocated)),
+                    file.path.display()
+                ));
+            }
+        }
+        out
+    }
+
+    fn csv(&self) -> String {
+        let mut out = String::from("type,path,depth,size,allocated,files\n");
+        for dir in &self.directories {
+            out.push_str(&format!(
+                "directory,{},{},{},{},{}\n",
+                csv_field(&dir.path.to_string_lossy()),
+                dir.depth,
+                dir.size,
+                dir.allocated,
+                dir.files
+            ));
+        }
+        for file in &self.largest_files {
+            let depth = file.path.strip_prefix(&self.root).map_or(0, |p| p.components().count());
+            out.push_str(&format!(
+                "file,{},{},{},{},1\n",
+                csv_field(&file.path.to_string_lossy()),
+                depth,
+                file.size,
+                file.allocated
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<OsString> {
+        line.split_whitespace().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn parses_scan_options() {
+        let command = parse(&args("scan /tmp --depth 2 --top=20 --format csv --size disk --exclude *.tmp")).unwrap();
+        assert_eq!(command.path, PathBuf::from("/tmp"));
+        assert_eq!((command.depth, command.top), (2, 20));
+        assert_eq!(command.format, Format::Csv);
+        assert_eq!(command.size_mode, SizeMode::OnDisk);
+        assert_eq!(command.options.exclude.patterns, vec!["*.tmp".to_string()]);
+        assert!(!command.options.exclude.measure);
+        assert!(parse(&args("scan /tmp --measure-excluded")).unwrap().options.exclude.measure);
+
+        assert!(parse(&args("scan")).is_err());
+        assert!(parse(&args("scan /tmp --format xml")).is_err());
+        assert!(parse(&args("scan /tmp --depth")).is_err());
+        assert!(parse(&args("scan /tmp /var")).is_err());
+        assert!(is_cli(&args("scan /tmp")));
+        assert!(!is_cli(&args("-psn_0_12345")));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let name = std::ffi::OsStr::from_bytes(b"/tmp/caf\xe9");
+            let command = parse(&[OsString::from("scan"), name.to_os_string()]).unwrap();
+            assert_eq!(command.path.as_os_str(), name);
+            assert!(parse(&[OsString::from("scan"), OsString::from("/tmp"), name.to_os_string()]).is_err());
+        }
+
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn json_report_tolerates_paths_that_are_not_utf8() {
+        use crate::core::scanner::tests::{fixture, scan_with};
+        use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt};
+
+        let root = fixture("cli-names", 0, 0, 0);
+        fs::write(root.join(OsStr::from_bytes(b"caf\xe9.txt")), vec![0u8; 10]).unwrap();
+
+        let command = parse(&[OsString::from("scan"), root.clone().into_os_string()]).unwrap();
+        let result = scan_with(&command.path, &command.options);
+        let json = serde_json::to_string(&Report::new(&result, &command)).unwrap();
+        assert!(json.contains("caf\u{fffd}.txt"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
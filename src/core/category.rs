@@ -0,0 +1,145 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use super::scanner::{SizeMode, TreeNode};
+
+/// Broad kinds of file, told apart by extension and, for caches and build
+/// output, by the directories they live in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    Video,
+    Audio,
+    Images,
+    Archives,
+    DiskImages,
+    Code,
+    Documents,
+    Binaries,
+    Caches,
+    Other,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Category::Video => "Video",
+            Category::Audio => "Audio",
+            Category::Images => "Images",
+            Category::Archives => "Archives",
+            Category::DiskImages => "VM & Disk Images",
+            Category::Code => "Code",
+            Category::Documents => "Documents",
+            Category::Binaries => "Binaries",
+            Category::Caches => "Caches & Build Output",
+            Category::Other => "Other",
+        })
+    }
+}
+
+/// Directories whose contents are regenerated rather than kept.
+const CACHE_DIRS: &[&str] = &[
+    "Caches", ".cache", "cache", "DerivedData", "node_modules", "__pycache__", ".gradle", ".npm", "target",
+];
+
+const EXTENSIONS: &[(Category, &[&str])] = &[
+    (Category::Video, &["mp4", "m4v", "mov", "mkv", "avi", "webm", "wmv", "flv", "mpg", "mpeg"]),
+    (Category::Audio, &["mp3", "m4a", "aac", "wav", "flac", "ogg", "opus", "aiff", "aif", "wma"]),
+    (
+        Category::Images,
+        &["jpg", "jpeg", "png", "gif", "heic", "heif", "tiff", "tif", "bmp", "webp", "raw", "cr2", "nef", "arw", "dng", "psd", "svg", "ico"],
+    ),
+    (Category::Archives, &["zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "lz4", "jar", "xip"]),
+    (Category::DiskImages, &["dmg", "iso", "img", "vmdk", "vdi", "vhd", "vhdx", "qcow2", "hdd", "ova", "sparseimage"]),
+    (
+        Category::Code,
+        &[
+            "rs", "c", "h", "cc", "cpp", "hpp", "m", "mm", "swift", "go", "java", "kt", "py", "rb", "php", "js", "jsx",
+            "ts", "tsx", "cs", "sh", "zsh", "lua", "scala", "dart", "vue", "html", "css", "scss", "sql", "toml", "yaml",
+            "yml", "json", "xml",
+        ],
+    ),
+    (
+        Category::Documents,
+        &["pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "pages", "numbers", "key", "odt", "ods", "odp", "rtf", "txt", "md", "csv", "epub"],
+    ),
+    (Category::Binaries, &["exe", "dll", "so", "dylib", "a", "o", "lib", "bin", "wasm", "class", "pyc", "rlib", "rmeta"]),
+    (Category::Caches, &["cache", "tmp"]),
+];
+
+/// The category of the file at `path`.
+pub fn classify(path: &Path) -> Category {
+    if is_cache(path) {
+        return Category::Caches;
+    }
+    let Some(extension) = path.extension().and_then(|e| e.to_str()) else {
+        return Category::Other;
+    };
+    let extension = extension.to_ascii_lowercase();
+    EXTENSIONS.iter()
+        .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+        .map_or(Category::Other, |(category, _)| *category)
+}
+
+/// Whether `path` is in, or is, a cache or build output directory.
+pub fn is_cache(path: &Path) -> bool {
+    path.iter().any(|component| component.to_str().is_some_and(|c| CACHE_DIRS.contains(&c)))
+}
+
+/// How much of `node` each category takes up, largest first, leaving out
+/// categories with nothing in them.
+pub fn breakdown(node: &TreeNode, mode: SizeMode) -> Vec<(Category, u64)> {
+    let mut totals: HashMap<Category, u64> = HashMap::new();
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if node.is_dir {
+            stack.extend(node.children.iter());
+        } else if node.size_in(mode) > 0 {
+            *totals.entry(classify(&node.path)).or_default() += node.size_in(mode);
+        }
+    }
+    let mut totals: Vec<(Category, u64)> = totals.into_iter().collect();
+    totals.sort_by_key(|&(category, size)| (std::cmp::Reverse(size), category));
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn classifies_and_totals_files() {
+        assert_eq!(classify(Path::new("/Movies/Holiday.MOV")), Category::Video);
+        assert_eq!(classify(Path::new("/src/main.rs")), Category::Code);
+        assert_eq!(classify(Path::new("/app/node_modules/left-pad/index.js")), Category::Caches);
+        assert_eq!(classify(Path::new("/VMs/ubuntu.qcow2")), Category::DiskImages);
+        assert_eq!(classify(Path::new("/bin/ls")), Category::Other);
+
+        let file = |path: &str, size| TreeNode {
+            path: PathBuf::from(path),
+            size,
+            allocated: size,
+            hardlinked: 0,
+            file_count: 1,
+            created: std::time::UNIX_EPOCH,
+            modified: std::time::UNIX_EPOCH,
+            newest: std::time::UNIX_EPOCH,
+            is_dir: false,
+            is_mount: false,
+            link_target: None,
+            skipped: false,
+            is_excluded: false,
+            excluded: 0,
+            partial: false,
+            inode: 0,
+            children: Vec::new(),
+        };
+        let mut root = file("/r", 0);
+        root.is_dir = true;
+        let mut sub = file("/r/sub", 0);
+        sub.is_dir = true;
+        sub.children = vec![file("/r/sub/a.mp4", 700), file("/r/sub/b.rs", 10)];
+        root.children = vec![sub, file("/r/c.mkv", 300), file("/r/empty.txt", 0)];
+
+        assert_eq!(breakdown(&root, SizeMode::Apparent), vec![(Category::Video, 1000), (Category::Code, 10)]);
+    }
+}
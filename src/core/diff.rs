@@ -0,0 +1,231 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use super::scanner::{FileEntry, SizeMode, TreeNode};
+
+/// How one path changed between two scans of the same root. Directory
+/// nodes aggregate everything below them.
+#[derive(Debug, Clone)]
+pub struct DiffNode {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub old_files: u64,
+    pub new_files: u64,
+    /// Bytes of new files plus the growth of files present in both scans.
+    pub added: u64,
+    /// Bytes of deleted files plus the shrinkage of files present in both.
+    pub removed: u64,
+    pub added_files: u64,
+    pub removed_files: u64,
+    /// Files present in both scans whose size changed.
+    pub changed_files: u64,
+    pub children: Vec<DiffNode>,
+}
+
+impl DiffNode {
+    pub fn delta(&self) -> i64 {
+        self.new_size as i64 - self.old_size as i64
+    }
+
+    pub fn find(&self, path: &Path) -> Option<&DiffNode> {
+        let relative = path.strip_prefix(&self.path).ok()?;
+        let mut node = self;
+        for component in relative.components() {
+            node = node.children.iter()
+                .find(|child| child.path.file_name() == Some(component.as_os_str()))?;
+        }
+        Some(node)
+    }
+
+    /// The node as a treemap entry, sized by its new size and carrying its
+    /// change in `delta`.
+    pub fn entry(&self, new: Option<&TreeNode>) -> FileEntry {
+        let mut entry = match new {
+            Some(node) => node.entry(),
+            None => FileEntry {
+                path: self.path.clone(),
+                size: 0,
+                allocated: 0,
+                hardlinked: 0,
+                created: std::time::UNIX_EPOCH,
+                modified: std::time::UNIX_EPOCH,
+                newest: std::time::UNIX_EPOCH,
+                is_dir: self.is_dir,
+                is_mount: false,
+                link_target: None,
+                skipped: false,
+                is_excluded: false,
+                excluded: 0,
+                partial: false,
+                delta: 0,
+            },
+        };
+        entry.delta = self.delta();
+        entry
+    }
+
+    /// Entries for the children of this node, using `new` (the new tree's
+    /// node for the same path) for everything that still exists.
+    pub fn child_entries(&self, new: Option<&TreeNode>) -> Vec<FileEntry> {
+        self.children.iter()
+            .map(|child| {
+                let new_child = new.and_then(|node| node.find(&child.path));
+                child.entry(new_child)
+            })
+            .collect()
+    }
+
+    /// Like `TreeNode::nested_entries`, for the directories below this node.
+    pub fn nested_entries(&self, levels: usize, new: Option<&TreeNode>) -> HashMap<PathBuf, Vec<FileEntry>> {
+        let mut nested = HashMap::new();
+        let mut stack: Vec<(&DiffNode, usize)> = self.children.iter().map(|child| (child, 1)).collect();
+        while let Some((node, level)) = stack.pop() {
+            if level > levels || node.children.is_empty() {
+                continue;
+            }
+            let new_node = new.and_then(|new| new.find(&node.path));
+            nested.insert(node.path.clone(), node.child_entries(new_node));
+            stack.extend(node.children.iter().map(|child| (child, level + 1)));
+        }
+        nested
+    }
+
+    /// The `limit` files (or childless entries) below this node that grew the
+    /// most, biggest first.
+    pub fn biggest_growers(&self, limit: usize, new: Option<&TreeNode>) -> Vec<FileEntry> {
+        let mut growers = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.children.is_empty() {
+                if node.delta() > 0 {
+                    growers.push(node);
+                }
+            } else {
+                stack.extend(node.children.iter());
+            }
+        }
+        growers.sort_by_key(|node| std::cmp::Reverse(node.delta()));
+        growers.into_iter()
+            .take(limit)
+            .map(|node| node.entry(new.and_then(|new| new.find(&node.path))))
+            .collect()
+    }
+}
+
+/// Compares two scans of the same root, measuring sizes in `mode`.
+pub fn diff_trees(old: &TreeNode, new: &TreeNode, mode: SizeMode) -> DiffNode {
+    diff(Some(old), Some(new), mode)
+}
+
+fn diff(old: Option<&TreeNode>, new: Option<&TreeNode>, mode: SizeMode) -> DiffNode {
+    let some = new.or(old).expect("at least one side of a diff exists");
+    let size = |node: Option<&TreeNode>| node.map_or(0, |n| n.size_in(mode));
+    let files = |node: Option<&TreeNode>| node.map_or(0, |n| n.file_count);
+
+    let mut result = DiffNode {
+        path: some.path.clone(),
+        is_dir: old.is_some_and(|n| n.is_dir) || new.is_some_and(|n| n.is_dir),
+        old_size: size(old),
+        new_size: size(new),
+        old_files: files(old),
+        new_files: files(new),
+        added: 0,
+        removed: 0,
+        added_files: 0,
+        removed_files: 0,
+        changed_files: 0,
+        children: Vec::new(),
+    };
+
+    // Childless nodes (files, skipped or unreadable directories) carry their
+    // bytes themselves; everything else is the sum of its children.
+    let old_leaf = old.filter(|n| n.children.is_empty());
+    let new_leaf = new.filter(|n| n.children.is_empty());
+    match (old_leaf, new_leaf) {
+        (Some(old), Some(new)) => {
+            let (old_size, new_size) = (old.size_in(mode), new.size_in(mode));
+            result.added += new_size.saturating_sub(old_size);
+            result.removed += old_size.saturating_sub(new_size);
+            result.added_files += new.file_count.saturating_sub(old.file_count);
+            result.removed_files += old.file_count.saturating_sub(new.file_count);
+            if old_size != new_size && old.file_count > 0 && new.file_count > 0 {
+                result.changed_files += 1;
+            }
+        }
+        (old, new) => {
+            result.added += size(new);
+            result.removed += size(old);
+            result.added_files += files(new);
+            result.removed_files += files(old);
+        }
+    }
+
+    let old_children: HashMap<&OsStr, &TreeNode> = old
+        .map(|n| n.children.iter().filter_map(|c| Some((c.path.file_name()?, c))).collect())
+        .unwrap_or_default();
+    let new_children = new.map(|n| n.children.as_slice()).unwrap_or_default();
+
+    let mut matched = 0;
+    for new_child in new_children {
+        let old_child = new_child.path.file_name().and_then(|name| old_children.get(name)).copied();
+        matched += usize::from(old_child.is_some());
+        result.children.push(diff(old_child, Some(new_child), mode));
+    }
+    if matched < old_children.len() {
+        let new_names: HashSet<_> = new_children.iter().filter_map(|c| c.path.file_name()).collect();
+        for (name, old_child) in &old_children {
+            if !new_names.contains(*name) {
+                result.children.push(diff(Some(old_child), None, mode));
+            }
+        }
+    }
+
+    for child in &result.children {
+        result.added += child.added;
+        result.removed += child.removed;
+        result.added_files += child.added_files;
+        result.removed_files += child.removed_files;
+        result.changed_files += child.changed_files;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::scanner::tests::{fixture, scan};
+    use std::fs;
+
+    #[test]
+    fn diff_tracks_added_removed_and_changed() {
+        let root = fixture("diff", 0, 0, 0);
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::write(root.join("logs").join("app.log"), vec![0u8; 1000]).unwrap();
+        fs::write(root.join("old.bin"), vec![0u8; 300]).unwrap();
+        let old = scan(&root, 2);
+
+        fs::write(root.join("logs").join("app.log"), vec![0u8; 5000]).unwrap();
+        fs::write(root.join("logs").join("new.log"), vec![0u8; 700]).unwrap();
+        fs::remove_file(root.join("old.bin")).unwrap();
+        let new = scan(&root, 2);
+
+        let diff = diff_trees(&old, &new, SizeMode::Apparent);
+        assert_eq!(diff.delta(), 5000 + 700 - 1000 - 300);
+        assert_eq!(diff.added, 4000 + 700);
+        assert_eq!(diff.removed, 300);
+        assert_eq!((diff.added_files, diff.removed_files, diff.changed_files), (1, 1, 1));
+
+        let removed = diff.find(&root.join("old.bin")).unwrap();
+        assert_eq!(removed.entry(None).delta, -300);
+        let growers = diff.biggest_growers(10, Some(&new));
+        let paths: Vec<_> = growers.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(paths, vec![root.join("logs").join("app.log"), root.join("logs").join("new.log")]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
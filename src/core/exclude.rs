@@ -0,0 +1,282 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Ignore files honored when `ExcludeRules::use_ignore_files` is set.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Which paths a scan leaves out of its totals.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExcludeRules {
+    /// Glob patterns. Patterns without a `/` match an entry's name at any
+    /// depth (`node_modules`, `*.tmp`); the rest match its full path
+    /// (`/Users/*/Library/Caches`, `**/target/debug`).
+    pub patterns: Vec<String>,
+    /// Absolute paths left out along with everything below them.
+    #[serde(with = "super::os_path::vec")]
+    pub paths: Vec<PathBuf>,
+    /// Also honor `.gitignore` and `.ignore` files found during the scan.
+    pub use_ignore_files: bool,
+    /// Walk excluded directories to report how much they hold. Off by
+    /// default, so excluding a directory saves reading it.
+    #[serde(default)]
+    pub measure: bool,
+}
+
+impl ExcludeRules {
+    /// Adds `rule` as a plain path if it is absolute and has no glob
+    /// characters, and as a pattern otherwise.
+    pub fn add(&mut self, rule: &str) {
+        let rule = rule.trim();
+        if rule.is_empty() {
+            return;
+        }
+        if rule.starts_with('/') && !rule.contains(['*', '?', '[']) {
+            let path = PathBuf::from(rule);
+            if !self.paths.contains(&path) {
+                self.paths.push(path);
+            }
+        } else if !self.patterns.iter().any(|p| p == rule) {
+            self.patterns.push(rule.to_string());
+        }
+    }
+
+    pub fn compile(&self) -> ExcludeMatcher {
+        let (path_globs, name_globs) = self.patterns.iter()
+            .map(|pattern| Glob::new(pattern))
+            .partition(|glob| glob.pattern.contains(&'/'));
+        ExcludeMatcher {
+            name_globs,
+            path_globs,
+            paths: self.paths.clone(),
+            use_ignore_files: self.use_ignore_files,
+        }
+    }
+}
+
+/// `ExcludeRules` with the globs parsed, ready to test paths during a walk.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeMatcher {
+    name_globs: Vec<Glob>,
+    path_globs: Vec<Glob>,
+    paths: Vec<PathBuf>,
+    use_ignore_files: bool,
+}
+
+impl ExcludeMatcher {
+    pub fn is_excluded(&self, path: &Path, is_dir: bool, ignores: &Option<Arc<IgnoreChain>>) -> bool {
+        if self.paths.iter().any(|p| p == path) {
+            return true;
+        }
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        if self.name_globs.iter().any(|glob| glob.matches(&name)) {
+            return true;
+        }
+        let full = path.to_string_lossy();
+        if self.path_globs.iter().any(|glob| glob.matches(&full)) {
+            return true;
+        }
+        IgnoreChain::is_ignored(ignores, path, is_dir)
+    }
+
+    /// Extends `parent` with the ignore files in `dir`, if honoring them.
+    pub fn ignores_for(&self, dir: &Path, parent: &Option<Arc<IgnoreChain>>) -> Option<Arc<IgnoreChain>> {
+        if !self.use_ignore_files {
+            return None;
+        }
+        let rules: Vec<IgnoreRule> = IGNORE_FILES.iter()
+            .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+            .flat_map(|contents| contents.lines().filter_map(IgnoreRule::parse).collect::<Vec<_>>())
+            .collect();
+        if rules.is_empty() {
+            return parent.clone();
+        }
+        Some(Arc::new(IgnoreChain { dir: dir.to_path_buf(), rules, parent: parent.clone() }))
+    }
+}
+
+/// The ignore files in effect for a directory: its own rules followed by
+/// those of its ancestors.
+#[derive(Debug)]
+pub struct IgnoreChain {
+    dir: PathBuf,
+    rules: Vec<IgnoreRule>,
+    parent: Option<Arc<IgnoreChain>>,
+}
+
+impl IgnoreChain {
+    /// Like git, the last matching rule in the deepest ignore file wins.
+    fn is_ignored(this: &Option<Arc<IgnoreChain>>, path: &Path, is_dir: bool) -> bool {
+        let mut current = this.as_deref();
+        while let Some(chain) = current {
+            if let Ok(relative) = path.strip_prefix(&chain.dir) {
+                let relative = relative.to_string_lossy();
+                let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+                let matched = chain.rules.iter().rev().find(|rule| {
+                    (!rule.dir_only || is_dir)
+                        && rule.glob.matches(if rule.anchored { &relative } else { &name })
+                });
+                if let Some(rule) = matched {
+                    return !rule.negated;
+                }
+            }
+            current = chain.parent.as_deref();
+        }
+        false
+    }
+}
+
+/// One line of a `.gitignore`-style file.
+#[derive(Debug)]
+struct IgnoreRule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    /// Matches the path relative to the ignore file's directory rather than
+    /// just the entry name.
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self { glob: Glob::new(line), negated, dir_only, anchored })
+    }
+}
+
+/// A shell-style glob. `*` and `?` stay within one path component, `**`
+/// spans any number of them and `[...]` matches a character class.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Self { pattern: pattern.chars().collect() }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        match_glob(&self.pattern, &text)
+    }
+}
+
+fn match_glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            // `**/` also matches no directories at all.
+            if rest.first() == Some(&'/') && match_glob(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| match_glob(rest, &text[i..]))
+        }
+        Some('*') => {
+            for i in 0..=text.len() {
+                if match_glob(&pattern[1..], &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => {
+            matches!(text.first(), Some(c) if *c != '/') && match_glob(&pattern[1..], &text[1..])
+        }
+        Some('[') => match (parse_class(&pattern[1..]), text.first()) {
+            (Some((matches_class, len)), Some(c)) => {
+                matches_class(*c) && match_glob(&pattern[len + 1..], &text[1..])
+            }
+            (Some(_), None) => false,
+            // No closing bracket: treat `[` literally.
+            (None, _) => text.first() == Some(&'[') && match_glob(&pattern[1..], &text[1..]),
+        },
+        Some(c) => text.first() == Some(c) && match_glob(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Parses a character class following its opening `[`. Returns a matcher
+/// and the number of pattern characters consumed, including the `]`.
+fn parse_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool, usize)> {
+    let negated = matches!(pattern.first(), Some('!') | Some('^'));
+    let start = usize::from(negated);
+    // A `]` right after the opening bracket is a literal member.
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|c| *c == ']')?;
+    let members = pattern[start..end].to_vec();
+
+    let matcher = move |c: char| {
+        let mut found = false;
+        let mut i = 0;
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == '-' {
+                found |= (members[i]..=members[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                found |= members[i] == c;
+                i += 1;
+            }
+        }
+        found != negated && c != '/'
+    };
+    Some((matcher, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching() {
+        assert!(Glob::new("*.tmp").matches("build.tmp"));
+        assert!(!Glob::new("*.tmp").matches("dir/build.tmp"));
+        assert!(Glob::new("/Users/*/Library/Caches").matches("/Users/me/Library/Caches"));
+        assert!(!Glob::new("/Users/*/Library/Caches").matches("/Users/me/x/Library/Caches"));
+        assert!(Glob::new("**/target/debug").matches("/src/app/target/debug"));
+        assert!(Glob::new("**/target").matches("target"));
+        assert!(Glob::new("file?.[a-c]").matches("file1.b"));
+        assert!(!Glob::new("file?.[!a-c]").matches("file1.b"));
+        assert!(Glob::new("[]x]").matches("]"));
+    }
+
+    #[test]
+    fn ignore_rules_follow_git_precedence() {
+        let dir = PathBuf::from("/repo");
+        let rules = ["target/", "*.log", "!keep.log", "/build", "docs/*.pdf"]
+            .into_iter()
+            .filter_map(IgnoreRule::parse)
+            .collect();
+        let chain = Some(Arc::new(IgnoreChain { dir, rules, parent: None }));
+
+        assert!(IgnoreChain::is_ignored(&chain, Path::new("/repo/a/target"), true));
+        assert!(!IgnoreChain::is_ignored(&chain, Path::new("/repo/a/target"), false));
+        assert!(IgnoreChain::is_ignored(&chain, Path::new("/repo/x/debug.log"), false));
+        assert!(!IgnoreChain::is_ignored(&chain, Path::new("/repo/keep.log"), false));
+        assert!(IgnoreChain::is_ignored(&chain, Path::new("/repo/build"), true));
+        assert!(!IgnoreChain::is_ignored(&chain, Path::new("/repo/src/build"), true));
+        assert!(IgnoreChain::is_ignored(&chain, Path::new("/repo/docs/a.pdf"), false));
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{ser::SerializeSeq, Serialize, Serializer};
+
+use super::scanner::{ScanError, ScanInfo, TreeNode};
+
+/// Formats a scanned tree can be written out in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// The tree as nested JSON objects.
+    #[default]
+    Json,
+    /// One row per entry: path, size, allocated, mtime, kind, depth.
+    Csv,
+    /// ncdu's JSON export format, readable with `ncdu -f`.
+    Ncdu,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [ExportFormat::Json, ExportFormat::Csv, ExportFormat::Ncdu];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json | ExportFormat::Ncdu => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExportFormat::Json => "JSON",
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Ncdu => "ncdu",
+        })
+    }
+}
+
+/// Writes `tree` to `writer` in `format`. `errors` are the entries the scan
+/// couldn't read, which ncdu marks as such.
+pub fn export(
+    writer: impl Write,
+    format: ExportFormat,
+    info: &ScanInfo,
+    tree: &TreeNode,
+    errors: &[ScanError],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    match format {
+        ExportFormat::Json => {
+            let export = JsonExport {
+                root: info.root.to_string_lossy(),
+                scanned_at: chrono::DateTime::<chrono::Utc>::from(info.scanned_at).to_rfc3339(),
+                host: &info.host,
+                tree: JsonNode(tree),
+            };
+            serde_json::to_writer_pretty(&mut writer, &export)?;
+            writeln!(writer)?;
+        }
+        ExportFormat::Csv => write_csv(&mut writer, tree)?,
+        ExportFormat::Ncdu => {
+            let header = NcduHeader {
+                progname: env!("CARGO_PKG_NAME"),
+                progver: env!("CARGO_PKG_VERSION"),
+                timestamp: unix_seconds(info.scanned_at),
+            };
+            let links = linked_allocations(tree);
+            let root = NcduNode { node: tree, errors, links: &links, mount: &tree.path, is_root: true };
+            serde_json::to_writer(&mut writer, &(1, 2, header, root))?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()
+}
+
+pub fn export_file(
+    path: &Path,
+    format: ExportFormat,
+    info: &ScanInfo,
+    tree: &TreeNode,
+    errors: &[ScanError],
+) -> io::Result<()> {
+    export(File::create(path)?, format, info, tree, errors)
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+pub fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn kind(node: &TreeNode) -> &'static str {
+    if node.link_target.is_some() {
+        "symlink"
+    } else if node.is_mount {
+        "mount"
+    } else if node.is_dir {
+        "directory"
+    } else {
+        "file"
+    }
+}
+
+fn name(node: &TreeNode) -> Cow<'_, str> {
+    node.path.file_name().unwrap_or(node.path.as_os_str()).to_string_lossy()
+}
+
+fn write_csv(writer: &mut impl Write, tree: &TreeNode) -> io::Result<()> {
+    writeln!(writer, "path,size,allocated,mtime,kind,depth")?;
+    let mut stack = vec![(tree, 0)];
+    while let Some((node, depth)) = stack.pop() {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            csv_field(&node.path.to_string_lossy()),
+            node.size,
+            node.allocated,
+            chrono::DateTime::<chrono::Utc>::from(node.modified)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            kind(node),
+            depth
+        )?;
+        stack.extend(node.children.iter().rev().map(|child| (child, depth + 1)));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    root: Cow<'a, str>,
+    scanned_at: String,
+    host: &'a str,
+    tree: JsonNode<'a>,
+}
+
+/// Serializes a node and everything below it without copying the tree.
+struct JsonNode<'a>(&'a TreeNode);
+
+impl Serialize for JsonNode<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Fields<'a> {
+            name: Cow<'a, str>,
+            kind: &'static str,
+            size: u64,
+            allocated: u64,
+            files: u64,
+            modified: u64,
+            #[serde(skip_serializing_if = "is_zero")]
+            excluded: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            link_target: Option<Cow<'a, str>>,
+            #[serde(skip_serializing_if = "is_false")]
+            partial: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            children: Option<Vec<JsonNode<'a>>>,
+        }
+
+        let node = self.0;
+        Fields {
+            name: name(node),
+            kind: kind(node),
+            size: node.size,
+            allocated: node.allocated,
+            files: node.file_count,
+            modified: unix_seconds(node.modified),
+            excluded: node.excluded,
+            link_target: node.link_target.as_ref().map(|target| target.to_string_lossy()),
+            partial: node.partial,
+            children: node.is_dir.then(|| node.children.iter().map(JsonNode).collect()),
+        }
+        .serialize(serializer)
+    }
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[derive(Serialize)]
+struct NcduHeader {
+    progname: &'static str,
+    progver: &'static str,
+    timestamp: u64,
+}
+
+/// A node in ncdu's layout: files are info objects, directories are arrays
+/// of their info object followed by their children.
+struct NcduNode<'a> {
+    node: &'a TreeNode,
+    errors: &'a [ScanError],
+    links: &'a LinkedAllocations<'a>,
+    /// The mount point, or the root, whose filesystem `node` is on.
+    mount: &'a Path,
+    is_root: bool,
+}
+
+/// Disk usage of each multiply-linked inode, by the mount point its
+/// filesystem is under and its inode number.
+type LinkedAllocations<'a> = HashMap<(&'a Path, u64), u64>;
+
+/// Looks up what each multiply-linked inode takes up on disk from the one
+/// link the scan charged it to; the tree has it as 0 on the others.
+fn linked_allocations(tree: &TreeNode) -> LinkedAllocations<'_> {
+    let mut links = HashMap::new();
+    let mut stack = vec![(tree, tree.path.as_path())];
+    while let Some((node, mount)) = stack.pop() {
+        if !node.is_dir && node.hardlinked > 0 && node.allocated > 0 {
+            links.insert((mount, node.inode), node.allocated);
+        }
+        for child in &node.children {
+            stack.push((child, if child.is_mount { child.path.as_path() } else { mount }));
+        }
+    }
+    links
+}
+
+#[derive(Serialize)]
+struct NcduInfo<'a> {
+    name: Cow<'a, str>,
+    asize: u64,
+    dsize: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    ino: u64,
+    #[serde(skip_serializing_if = "is_false")]
+    hlnkc: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    read_error: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excluded: Option<&'static str>,
+    #[serde(skip_serializing_if = "is_false")]
+    notreg: bool,
+    mtime: u64,
+}
+
+impl NcduNode<'_> {
+    fn info(&self) -> NcduInfo<'_> {
+        let node = self.node;
+        // ncdu wants each directory's own size and adds up the rest itself.
+        let (asize, dsize) = if node.is_dir {
+            let (size, allocated) = node.children.iter()
+                .fold((0, 0), |(s, a), child| (s + child.size, a + child.allocated));
+            (node.size.saturating_sub(size), node.allocated.saturating_sub(allocated))
+        } else if node.hardlinked > 0 {
+            // Only the first link is charged to `size` and `allocated`; ncdu
+            // charges each inode once by itself, so every link gets the
+            // file's sizes.
+            let allocated = self.links.get(&(self.mount, node.inode)).copied().unwrap_or(node.allocated);
+            (node.hardlinked, allocated)
+        } else {
+            (node.size, node.allocated)
+        };
+        NcduInfo {
+            name: if self.is_root { node.path.to_string_lossy() } else { name(node) },
+            asize,
+            dsize,
+            ino: node.inode,
+            hlnkc: node.hardlinked > 0 && !node.is_dir,
+            read_error: self.errors.iter().any(|error| error.path == node.path),
+            excluded: if node.is_excluded {
+                Some("pattern")
+            } else if node.is_mount && node.skipped {
+                Some("otherfs")
+            } else {
+                None
+            },
+            notreg: !node.is_dir && node.link_target.is_some(),
+            mtime: unix_seconds(node.modified),
+        }
+    }
+}
+
+impl Serialize for NcduNode<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.node.is_dir {
+            return self.info().serialize(serializer);
+        }
+        let mut seq = serializer.serialize_seq(Some(self.node.children.len() + 1))?;
+        seq.serialize_element(&self.info())?;
+        for child in &self.node.children {
+            let mount = if child.is_mount { &child.path } else { self.mount };
+            seq.serialize_element(&NcduNode { node: child, errors: self.errors, links: self.links, mount, is_root: false })?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::scanner::tests::{fixture, scan_with};
+    use crate::core::scanner::ScanOptions;
+    use std::fs;
+
+    #[test]
+    fn exports_every_format() {
+        let root = fixture("export", 0, 0, 0);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub").join("a,b.txt"), vec![0u8; 1234]).unwrap();
+        fs::write(root.join("top.bin"), vec![0u8; 10]).unwrap();
+
+        let result = scan_with(&root, &ScanOptions::default());
+        let render = |format| {
+            let mut out = Vec::new();
+            export(&mut out, format, &result.info, &result.tree, &result.errors).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&render(ExportFormat::Json)).unwrap();
+        assert_eq!(json["tree"]["size"], 1244);
+        assert_eq!(json["tree"]["children"].as_array().unwrap().len(), 2);
+
+        let csv = render(ExportFormat::Csv);
+        assert_eq!(csv.lines().count(), 1 + 4);
+        assert!(csv.contains(&format!("\"{}\",1234,", root.join("sub").join("a,b.txt").display())));
+        assert!(csv.contains(",file,2\n"));
+
+        let ncdu: serde_json::Value = serde_json::from_str(&render(ExportFormat::Ncdu)).unwrap();
+        assert_eq!((ncdu[0].as_u64(), ncdu[1].as_u64()), (Some(1), Some(2)));
+        assert_eq!(ncdu[3][0]["name"], root.to_string_lossy().as_ref());
+        let sizes: u64 = ncdu[3].as_array().unwrap()[1..].iter()
+            .map(|entry| match entry.as_array() {
+                Some(dir) => dir[1..].iter().map(|e| e["asize"].as_u64().unwrap()).sum(),
+                None => entry["asize"].as_u64().unwrap(),
+            })
+            .sum();
+        assert_eq!(sizes, 1244);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ncdu_gives_every_hard_link_the_same_sizes() {
+        let root = fixture("export-links", 0, 0, 0);
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("original"), vec![1u8; 10_000]).unwrap();
+        fs::hard_link(root.join("original"), root.join("sub").join("link")).unwrap();
+
+        let result = scan_with(&root, &ScanOptions::default());
+        let mut out = Vec::new();
+        export(&mut out, ExportFormat::Ncdu, &result.info, &result.tree, &result.errors).unwrap();
+        let ncdu: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let mut links = Vec::new();
+        let mut stack = vec![&ncdu[3]];
+        while let Some(entry) = stack.pop() {
+            match entry.as_array() {
+                Some(dir) => stack.extend(&dir[1..]),
+                None if entry["hlnkc"] == true => links.push((entry["asize"].clone(), entry["dsize"].clone())),
+                None => {}
+            }
+        }
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0], links[1]);
+        assert_eq!(links[0].0, 10_000);
+        assert!(links[0].1.as_u64().unwrap() > 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flate2::read::GzDecoder;
+use serde_json::Value;
+
+use super::scanner::{ScanError, ScanErrorKind, ScanInfo, ScanOptions, TreeNode};
+use super::snapshot::Snapshot;
+
+/// Host recorded for imports, which don't say where they were taken.
+const UNKNOWN_HOST: &str = "unknown host";
+
+/// Reads an `ncdu -o` dump (optionally gzip'd) or a `du -ab` listing,
+/// telling them apart by their contents.
+pub fn import_file(path: &Path) -> io::Result<Snapshot> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let start = reader.fill_buf()?;
+    if start.starts_with(&[0x1f, 0x8b]) {
+        return import_ncdu(BufReader::new(GzDecoder::new(reader)));
+    }
+    if start.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[') {
+        return import_ncdu(reader);
+    }
+    // du doesn't record when it ran; the listing's mtime is the best guess.
+    let scanned_at = fs::metadata(path)?.modified().unwrap_or(SystemTime::now());
+    import_du(reader, scanned_at)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn info(root: &Path, scanned_at: SystemTime) -> ScanInfo {
+    ScanInfo {
+        root: root.to_path_buf(),
+        scanned_at,
+        host: UNKNOWN_HOST.to_string(),
+        options: ScanOptions::default(),
+    }
+}
+
+fn empty_node(path: PathBuf, is_dir: bool, modified: SystemTime) -> TreeNode {
+    TreeNode {
+        path,
+        size: 0,
+        allocated: 0,
+        hardlinked: 0,
+        file_count: 0,
+        created: modified,
+        modified,
+        newest: modified,
+        is_dir,
+        is_mount: false,
+        link_target: None,
+        skipped: false,
+        is_excluded: false,
+        excluded: 0,
+        partial: false,
+        inode: 0,
+        children: Vec::new(),
+    }
+}
+
+/// Reads ncdu's JSON export format: `[major, minor, {header}, root]`, where
+/// directories are arrays of their info object followed by their children.
+pub fn import_ncdu(reader: impl Read) -> io::Result<Snapshot> {
+    let value: Value = serde_json::from_reader(reader)?;
+    let dump = value.as_array().ok_or_else(|| invalid("not an ncdu export"))?;
+    if dump.first().and_then(Value::as_u64) != Some(1) || dump.len() < 4 {
+        return Err(invalid("not an ncdu export, or an unsupported version of it"));
+    }
+    let scanned_at = dump[2]["timestamp"].as_u64()
+        .map_or(UNIX_EPOCH, |secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+    let mut import = NcduImport { errors: Vec::new(), inodes: HashSet::new() };
+    let tree = import.node(&dump[3], None, 0)?;
+    import.errors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(Snapshot { info: info(&tree.path, scanned_at), tree, errors: import.errors })
+}
+
+struct NcduImport {
+    errors: Vec<ScanError>,
+    /// (device, inode) of every hard-linked file charged so far.
+    inodes: HashSet<(u64, u64)>,
+}
+
+impl NcduImport {
+    fn node(&mut self, value: &Value, parent: Option<&Path>, parent_dev: u64) -> io::Result<TreeNode> {
+        let (info, children) = match value {
+            Value::Array(items) => (
+                items.first().ok_or_else(|| invalid("ncdu directory without an info object"))?,
+                Some(&items[1..]),
+            ),
+            _ => (value, None),
+        };
+        let name = info["name"].as_str().ok_or_else(|| invalid("ncdu entry without a name"))?;
+        let path = match parent {
+            Some(parent) => parent.join(name),
+            None => PathBuf::from(name),
+        };
+        let modified = info["mtime"].as_u64()
+            .map_or(UNIX_EPOCH, |secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let dev = info["dev"].as_u64().unwrap_or(parent_dev);
+        let asize = info["asize"].as_u64().unwrap_or(0);
+        let dsize = info["dsize"].as_u64().unwrap_or(0);
+
+        let mut node = empty_node(path, children.is_some(), modified);
+        node.inode = info["ino"].as_u64().unwrap_or(0);
+        node.is_mount = node.is_dir && parent.is_some() && dev != parent_dev;
+        node.size = asize;
+        node.allocated = dsize;
+        if !node.is_dir {
+            node.file_count = 1;
+        }
+        if info["read_error"].as_bool() == Some(true) {
+            self.errors.push(ScanError {
+                path: node.path.clone(),
+                kind: ScanErrorKind::Io("could not be read (reported by ncdu)".to_string()),
+            });
+        }
+        if info["hlnkc"].as_bool() == Some(true) {
+            node.hardlinked = asize;
+            if !self.inodes.insert((dev, node.inode)) {
+                node.size = 0;
+                node.allocated = 0;
+            }
+        }
+        match info["excluded"].as_str() {
+            Some("otherfs" | "othfs" | "kernfs") => {
+                node.is_dir = true;
+                node.is_mount = true;
+                node.skipped = true;
+                node.file_count = 0;
+            }
+            Some(_) => node.exclude(),
+            None => {}
+        }
+
+        for child in children.unwrap_or_default() {
+            let mut child = self.node(child, Some(&node.path), dev)?;
+            if child.is_excluded {
+                child.exclude();
+            }
+            node.size += child.size;
+            node.allocated += child.allocated;
+            node.hardlinked += child.hardlinked;
+            node.excluded += child.excluded;
+            node.file_count += child.file_count;
+            node.newest = node.newest.max(child.newest);
+            node.children.push(child);
+        }
+        Ok(node)
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    std::str::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// Reads `du -ab` output: one `<bytes>\t<path>` line per entry, with every
+/// directory's total after its contents and the root last.
+///
+/// du doesn't say which entries are directories, so only those with
+/// something listed below them are. An empty directory comes out as a file
+/// of its directory's block size and adds one to the file counts.
+pub fn import_du(reader: impl BufRead, scanned_at: SystemTime) -> io::Result<Snapshot> {
+    let mut entries = Vec::new();
+    // du prints paths as raw bytes, which needn't be UTF-8.
+    for (number, line) in reader.split(b'\n').enumerate() {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let parsed = line.iter().position(|b| *b == b'\t').and_then(|tab| {
+            let size = std::str::from_utf8(&line[..tab]).ok()?.trim().parse::<u64>().ok()?;
+            Some((size, path_from_bytes(&line[tab + 1..])?))
+        });
+        let (size, path) = parsed.ok_or_else(|| {
+            invalid(format!("line {} is not `<bytes>\\t<path>`: {}", number + 1, String::from_utf8_lossy(&line)))
+        })?;
+        entries.push((path, size));
+    }
+    let root = entries.last().map(|(path, _)| path.clone()).ok_or_else(|| invalid("empty du listing"))?;
+    if let Some((path, _)) = entries.iter().find(|(path, _)| !path.starts_with(&root)) {
+        return Err(invalid(format!(
+            "{} is not below {}; import one du listing per directory",
+            path.display(),
+            root.display()
+        )));
+    }
+
+    let dirs: HashSet<PathBuf> = entries.iter()
+        .filter_map(|(path, _)| path.parent().filter(|_| *path != root).map(Path::to_path_buf))
+        .collect();
+    let mut nodes: HashMap<PathBuf, TreeNode> = HashMap::new();
+    for (path, size) in &entries {
+        let mut node = empty_node(path.clone(), dirs.contains(path), scanned_at);
+        // du's directory sizes already include everything below them.
+        node.size = *size;
+        node.allocated = *size;
+        nodes.insert(path.clone(), node);
+    }
+
+    // Attach the deepest entries first so each node is complete by the time
+    // it moves into its parent.
+    let mut paths: Vec<PathBuf> = nodes.keys().filter(|path| **path != root).cloned().collect();
+    paths.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+    for path in paths {
+        let mut node = nodes.remove(&path).expect("every path has a node");
+        if !node.is_dir {
+            node.file_count = 1;
+        }
+        let parent = path.parent().expect("paths below the root have a parent");
+        let parent = nodes.get_mut(parent)
+            .ok_or_else(|| invalid(format!("{} is missing from the listing", parent.display())))?;
+        parent.file_count += node.file_count;
+        parent.children.push(node);
+    }
+
+    let tree = nodes.remove(&root).expect("the root has a node");
+    Ok(Snapshot { info: info(&root, scanned_at), tree, errors: Vec::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_ncdu_dumps() {
+        let dump = r#"[1,2,{"progname":"ncdu","progver":"1.19","timestamp":1700000000},
+            [{"name":"/srv","asize":4096,"dsize":4096,"dev":1},
+              {"name":"a.log","asize":1000,"dsize":4096,"mtime":1690000000},
+              [{"name":"data","asize":4096,"dsize":4096},
+                {"name":"x","asize":500,"dsize":4096,"ino":7,"hlnkc":true},
+                {"name":"y","asize":500,"dsize":4096,"ino":7,"hlnkc":true},
+                {"name":"secret","read_error":true}],
+              {"name":"cache","excluded":"pattern"},
+              {"name":"mnt","excluded":"otherfs"}]]"#;
+        let snapshot = import_ncdu(dump.as_bytes()).unwrap();
+        let tree = &snapshot.tree;
+        assert_eq!(snapshot.info.root, PathBuf::from("/srv"));
+        assert_eq!(tree.size, 4096 + 1000 + 4096 + 500);
+        assert_eq!(tree.file_count, 4);
+        assert_eq!(tree.children.len(), 4);
+        assert!(tree.find(Path::new("/srv/mnt")).unwrap().skipped);
+        assert!(tree.find(Path::new("/srv/cache")).unwrap().is_excluded);
+        assert_eq!(tree.find(Path::new("/srv/data")).unwrap().hardlinked, 1000);
+        assert_eq!(snapshot.errors[0].path, PathBuf::from("/srv/data/secret"));
+
+        assert!(import_ncdu("{}".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn imports_du_listings() {
+        let listing = "100\t./docs/readme.md\n4196\t./docs\n4096\t./empty\n50\t./top.txt\n12438\t.\n";
+        let snapshot = import_du(listing.as_bytes(), UNIX_EPOCH).unwrap();
+        let tree = &snapshot.tree;
+        assert_eq!(tree.path, PathBuf::from("."));
+        assert_eq!(tree.size, 12438);
+        // readme.md, top.txt and the empty directory, which du can't tell
+        // apart from a file.
+        assert_eq!(tree.file_count, 3);
+        assert!(!tree.find(Path::new("./empty")).unwrap().is_dir);
+        let docs = tree.find(Path::new("./docs")).unwrap();
+        assert!(docs.is_dir);
+        assert_eq!(docs.children[0].size, 100);
+
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            let listing = b"10\t./caf\xe9.txt\r\n10\t.\r\n";
+            let tree = import_du(&listing[..], UNIX_EPOCH).unwrap().tree;
+            assert!(tree.find(&Path::new(".").join(OsStr::from_bytes(b"caf\xe9.txt"))).is_some());
+        }
+
+        assert!(import_du("12\t/a\n34\t/b\n".as_bytes(), UNIX_EPOCH).is_err());
+        assert!(import_du("garbage\n".as_bytes(), UNIX_EPOCH).is_err());
+    }
+}
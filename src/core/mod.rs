@@ -1 +1,10 @@
+pub mod category;
+pub mod diff;
+pub mod exclude;
+pub mod export;
+pub mod import;
+pub mod os_path;
 pub mod scanner;
+pub mod snapshot;
+pub mod walker;
+pub mod watcher;
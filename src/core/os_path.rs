@@ -0,0 +1,93 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A path or file name as saved: text when it is valid UTF-8 and raw bytes
+/// otherwise, so it loads back exactly as it was on disk. serde's own
+/// `PathBuf` encoding refuses names that aren't UTF-8.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OsText {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl OsText {
+    pub fn new(name: &OsStr) -> io::Result<Self> {
+        if let Some(name) = name.to_str() {
+            return Ok(OsText::Text(name.to_string()));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            Ok(OsText::Bytes(name.as_bytes().to_vec()))
+        }
+        #[cfg(not(unix))]
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not valid Unicode and can't be saved", name.to_string_lossy()),
+        ))
+    }
+
+    pub fn to_os_string(&self) -> io::Result<OsString> {
+        match self {
+            OsText::Text(name) => Ok(name.into()),
+            #[cfg(unix)]
+            OsText::Bytes(name) => {
+                use std::os::unix::ffi::OsStringExt;
+                Ok(OsString::from_vec(name.clone()))
+            }
+            #[cfg(not(unix))]
+            OsText::Bytes(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "saved path is not valid on this platform",
+            )),
+        }
+    }
+}
+
+/// `#[serde(with = "os_path")]` for a `PathBuf`.
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    OsText::new(path.as_os_str()).map_err(ser::Error::custom)?.serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    OsText::deserialize(deserializer)?.to_os_string().map(PathBuf::from).map_err(de::Error::custom)
+}
+
+/// `#[serde(with = "os_path::option")]` for an `Option<PathBuf>`.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = path.as_deref().map(|path| OsText::new(path.as_os_str())).transpose();
+        text.map_err(ser::Error::custom)?.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+        let text = Option::<OsText>::deserialize(deserializer)?;
+        text.map(|text| text.to_os_string().map(PathBuf::from)).transpose().map_err(de::Error::custom)
+    }
+}
+
+/// `#[serde(with = "os_path::vec")]` for a `Vec<PathBuf>`.
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        let texts: io::Result<Vec<OsText>> = paths.iter().map(|path| OsText::new(path.as_os_str())).collect();
+        texts.map_err(ser::Error::custom)?.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+        Vec::<OsText>::deserialize(deserializer)?
+            .iter()
+            .map(|text| text.to_os_string().map(PathBuf::from))
+            .collect::<io::Result<_>>()
+            .map_err(de::Error::custom)
+    }
+}
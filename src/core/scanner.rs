@@ -1,83 +1,923 @@
-use std::{path::{Path, PathBuf}, time::SystemTime};
-use walkdir::WalkDir;
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::exclude::ExcludeRules;
+use super::walker::ParallelWalk;
+
+/// Which of an entry's sizes to report: the byte length of its contents or
+/// the space its blocks actually take up on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeMode {
+    #[default]
+    Apparent,
+    OnDisk,
+}
+
+impl SizeMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            SizeMode::Apparent => "Apparent",
+            SizeMode::OnDisk => "On Disk",
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            SizeMode::Apparent => SizeMode::OnDisk,
+            SizeMode::OnDisk => SizeMode::Apparent,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct FileEntry {
     pub path: PathBuf,
     pub size: u64,
+    /// Bytes allocated on disk, which is less than `size` for sparse files
+    /// and more for small files rounded up to whole blocks.
+    pub allocated: u64,
+    /// Bytes of this entry that live on inodes with more than one hard link.
+    pub hardlinked: u64,
     pub created: SystemTime,
     pub modified: SystemTime,
+    /// Most recent `modified` at or below this entry.
+    pub newest: SystemTime,
     pub is_dir: bool,
+    /// Set for directories that live on a different filesystem than their
+    /// parent.
+    pub is_mount: bool,
+    /// Where the entry points to, if it is a symbolic link.
+    pub link_target: Option<PathBuf>,
+    /// Set for directories the scan deliberately did not descend into.
+    pub skipped: bool,
+    /// Set for entries left out by the scan's exclude rules.
+    pub is_excluded: bool,
+    /// Bytes at or below this entry left out by the exclude rules.
+    pub excluded: u64,
+    /// Set when the scan was cancelled before this entry was fully walked.
+    pub partial: bool,
+    /// Change in size against a compared scan; zero outside diff mode.
+    pub delta: i64,
 }
 
-#[derive(Debug, Clone)]
+impl FileEntry {
+    pub fn size_in(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.size,
+            SizeMode::OnDisk => self.allocated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ScanProgress {
-    pub total_files: usize,
-    pub scanned_files: usize,
+    /// Number of entries directly below the scan root.
+    pub total_entries: usize,
+    /// Number of those entries whose subtree has been fully scanned.
+    pub scanned_entries: usize,
+    pub files_scanned: u64,
     pub current_path: Option<PathBuf>,
     pub total_size: u64,
+    pub elapsed: Duration,
 }
 
-impl Default for ScanProgress {
-    fn default() -> Self {
-        Self {
-            total_files: 0,
-            scanned_files: 0,
-            current_path: None,
-            total_size: 0,
-        }
-    }
-}
-
-pub fn get_dir_size(path: &Path) -> u64 {
-    WalkDir::new(path)
-        .into_iter()
-        .filter_map(|entry| entry.ok())
-        .filter_map(|entry| entry.metadata().ok())
-        .filter(|metadata| metadata.is_file())
-        .map(|metadata| metadata.len())
-        .sum()
-}
-
-pub fn scan_directory(path: &Path, progress: &mut ScanProgress) -> Vec<FileEntry> {
-    let mut entries = Vec::new();
-    
-    // First count total files for progress
-    progress.total_files = WalkDir::new(path)
-        .min_depth(1)
-        .max_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .count();
-    
-    progress.scanned_files = 0;
-    
-    for entry in WalkDir::new(path)
-        .min_depth(1)
-        .max_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        progress.current_path = Some(entry.path().to_path_buf());
-        progress.scanned_files += 1;
-        
-        let metadata = entry.metadata().unwrap();
-        let size = if metadata.is_file() {
-            metadata.len()
+impl ScanProgress {
+    /// Fraction of the top-level entries that have been scanned, in `0.0..=1.0`.
+    pub fn fraction(&self) -> f32 {
+        if self.total_entries == 0 {
+            0.0
         } else {
-            get_dir_size(&entry.path())
+            self.scanned_entries as f32 / self.total_entries as f32
+        }
+    }
+
+    /// Bytes seen per second since the scan started.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.total_size as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Why an entry could not be read during a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanErrorKind {
+    PermissionDenied,
+    NotFound,
+    Io(String),
+}
+
+/// An entry the scan could not read. Totals don't include anything below it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanError {
+    #[serde(with = "super::os_path")]
+    pub path: PathBuf,
+    pub kind: ScanErrorKind,
+}
+
+impl ScanError {
+    pub fn new(path: &Path, error: &io::Error) -> Self {
+        let kind = match error.kind() {
+            io::ErrorKind::PermissionDenied => ScanErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => ScanErrorKind::NotFound,
+            _ => ScanErrorKind::Io(error.to_string()),
         };
-        
-        progress.total_size += size;
-        
-        entries.push(FileEntry {
-            path: entry.path().to_path_buf(),
-            size,
+        Self { path: path.to_path_buf(), kind }
+    }
+}
+
+impl fmt::Display for ScanErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanErrorKind::PermissionDenied => write!(f, "permission denied"),
+            ScanErrorKind::NotFound => write!(f, "not found"),
+            ScanErrorKind::Io(message) => write!(f, "I/O error: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+/// Cooperative cancellation flag for a running scan. Clones share the same
+/// flag, so the UI can keep one half and hand the other to the scanner.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A node of the scanned directory tree. Directory nodes carry the
+/// aggregated size and file count of everything below them, so the UI can
+/// navigate the tree without touching the filesystem again.
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    pub path: PathBuf,
+    pub size: u64,
+    pub allocated: u64,
+    /// Bytes below this node stored on inodes with more than one hard link.
+    /// Only the first link the scan reaches is charged to `size` and
+    /// `allocated`; later links still count here.
+    pub hardlinked: u64,
+    pub file_count: u64,
+    pub created: SystemTime,
+    pub modified: SystemTime,
+    /// Most recent `modified` at or below this node, so a directory counts
+    /// as recently changed when anything inside it did.
+    pub newest: SystemTime,
+    pub is_dir: bool,
+    /// Set for directories that live on a different filesystem than their
+    /// parent.
+    pub is_mount: bool,
+    /// Where the node points to, if it is a symbolic link. How its size is
+    /// counted depends on the scan's `SymlinkPolicy`.
+    pub link_target: Option<PathBuf>,
+    /// Set for directories the scan deliberately did not descend into, such
+    /// as mount points on another filesystem or symlinks that would loop.
+    pub skipped: bool,
+    /// Set for entries left out by the scan's exclude rules. Their bytes
+    /// move from `size` to `excluded` and nothing below them is kept.
+    pub is_excluded: bool,
+    /// Bytes at or below this node left out by the exclude rules.
+    pub excluded: u64,
+    /// Set when the scan was cancelled before this subtree was fully walked,
+    /// so `size` and `file_count` only cover what was seen.
+    pub partial: bool,
+    /// Inode number, or 0 where the platform has none. Together with
+    /// `modified` it tells a rescan whether a directory's listing changed.
+    pub inode: u64,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    pub fn leaf(path: PathBuf, metadata: &fs::Metadata) -> Self {
+        let is_file = metadata.is_file();
+        let modified = metadata.modified().unwrap_or(SystemTime::now());
+        Self {
+            path,
+            size: if is_file { metadata.len() } else { 0 },
+            allocated: allocated_size(metadata),
+            hardlinked: 0,
+            file_count: u64::from(is_file),
             created: metadata.created().unwrap_or(SystemTime::now()),
-            modified: metadata.modified().unwrap_or(SystemTime::now()),
+            modified,
+            newest: modified,
             is_dir: metadata.is_dir(),
+            is_mount: false,
+            link_target: None,
+            skipped: false,
+            is_excluded: false,
+            excluded: 0,
+            partial: false,
+            inode: inode_key(metadata).map_or(0, |(_, ino)| ino),
+            children: Vec::new(),
+        }
+    }
+
+    /// Whether a rescan may reuse this directory's cached listing for
+    /// `current`, the fresh node for the same path.
+    pub fn listing_unchanged(&self, current: &TreeNode) -> bool {
+        self.is_dir
+            && current.is_dir
+            && self.inode != 0
+            && self.inode == current.inode
+            && self.modified == current.modified
+            && !self.partial
+            && !self.skipped
+            && !self.is_excluded
+            // Unreadable directories are listed again so their error is
+            // reported, and empty ones are cheap to list anyway.
+            && !self.children.is_empty()
+    }
+
+    /// Detaches the node at `path` from the tree and takes its bytes and
+    /// files out of every ancestor's totals.
+    pub fn remove(&mut self, path: &Path) -> Option<TreeNode> {
+        let relative = path.strip_prefix(&self.path).ok()?;
+        let mut components = relative.components();
+        let name = components.next()?.as_os_str();
+        let index = self.children.iter().position(|child| child.path.file_name() == Some(name))?;
+        let removed = if components.as_path().as_os_str().is_empty() {
+            self.children.remove(index)
+        } else {
+            self.children[index].remove(path)?
+        };
+        self.size = self.size.saturating_sub(removed.size);
+        self.allocated = self.allocated.saturating_sub(removed.allocated);
+        self.hardlinked = self.hardlinked.saturating_sub(removed.hardlinked);
+        self.excluded = self.excluded.saturating_sub(removed.excluded);
+        self.file_count = self.file_count.saturating_sub(removed.file_count);
+        self.newest = self.children.iter().map(|child| child.newest).fold(self.modified, SystemTime::max);
+        Some(removed)
+    }
+
+    /// Puts `node` into the tree under its parent directory, replacing any
+    /// node already at its path, and adds its bytes and files to every
+    /// ancestor's totals. Returns false if the parent isn't in the tree.
+    pub fn insert(&mut self, node: TreeNode) -> bool {
+        if node.path == self.path {
+            return false;
+        }
+        self.remove(&node.path);
+        self.attach(node)
+    }
+
+    fn attach(&mut self, node: TreeNode) -> bool {
+        let Some(parent) = node.path.parent() else {
+            return false;
+        };
+        let (size, allocated, hardlinked, excluded, file_count, newest) =
+            (node.size, node.allocated, node.hardlinked, node.excluded, node.file_count, node.newest);
+        if parent == self.path {
+            self.children.push(node);
+        } else {
+            let Some(name) = parent.strip_prefix(&self.path).ok().and_then(|r| r.components().next()) else {
+                return false;
+            };
+            let Some(child) = self.children.iter_mut()
+                .find(|child| child.path.file_name() == Some(name.as_os_str()))
+            else {
+                return false;
+            };
+            if !child.attach(node) {
+                return false;
+            }
+        }
+        self.size += size;
+        self.allocated += allocated;
+        self.hardlinked += hardlinked;
+        self.excluded += excluded;
+        self.file_count += file_count;
+        self.newest = self.newest.max(newest);
+        true
+    }
+
+    /// Whether an excluded directory at or below this node was left unread,
+    /// so `excluded` doesn't include what is inside it.
+    pub fn excludes_unmeasured(&self) -> bool {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.is_excluded && node.is_dir && node.skipped {
+                return true;
+            }
+            stack.extend(node.children.iter());
+        }
+        false
+    }
+
+    /// Moves this node's bytes into the excluded bucket and drops
+    /// everything below it.
+    pub fn exclude(&mut self) {
+        self.is_excluded = true;
+        self.excluded += self.size;
+        self.size = 0;
+        self.allocated = 0;
+        self.hardlinked = 0;
+        self.file_count = 0;
+        self.newest = self.modified;
+        self.children.clear();
+    }
+
+    pub fn entry(&self) -> FileEntry {
+        FileEntry {
+            path: self.path.clone(),
+            size: self.size,
+            allocated: self.allocated,
+            hardlinked: self.hardlinked,
+            created: self.created,
+            modified: self.modified,
+            newest: self.newest,
+            is_dir: self.is_dir,
+            is_mount: self.is_mount,
+            link_target: self.link_target.clone(),
+            skipped: self.skipped,
+            is_excluded: self.is_excluded,
+            excluded: self.excluded,
+            partial: self.partial,
+            delta: 0,
+        }
+    }
+
+    pub fn child_entries(&self) -> Vec<FileEntry> {
+        self.children.iter().map(TreeNode::entry).collect()
+    }
+
+    /// Child entries of the directories below this node, down to `levels`
+    /// levels deep, keyed by directory path.
+    pub fn nested_entries(&self, levels: usize) -> HashMap<PathBuf, Vec<FileEntry>> {
+        let mut nested = HashMap::new();
+        let mut stack: Vec<(&TreeNode, usize)> = self.children.iter().map(|child| (child, 1)).collect();
+        while let Some((node, level)) = stack.pop() {
+            if level > levels || node.children.is_empty() {
+                continue;
+            }
+            nested.insert(node.path.clone(), node.child_entries());
+            stack.extend(node.children.iter().map(|child| (child, level + 1)));
+        }
+        nested
+    }
+
+    /// Looks up the node for `path`, which must be this node's path or lie
+    /// below it.
+    pub fn find(&self, path: &Path) -> Option<&TreeNode> {
+        let relative = path.strip_prefix(&self.path).ok()?;
+        let mut node = self;
+        for component in relative.components() {
+            node = node.children.iter()
+                .find(|child| child.path.file_name() == Some(component.as_os_str()))?;
+        }
+        Some(node)
+    }
+
+    pub fn size_in(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.size,
+            SizeMode::OnDisk => self.allocated,
+        }
+    }
+
+    /// Returns every mount point below this node that the scan did not
+    /// descend into.
+    pub fn skipped_mounts(&self) -> Vec<PathBuf> {
+        let mut mounts = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.is_mount && node.skipped {
+                mounts.push(node.path.clone());
+            }
+            stack.extend(node.children.iter());
+        }
+        mounts.sort();
+        mounts
+    }
+
+    /// Returns the `limit` largest regular files anywhere below this node.
+    pub fn largest_files(&self, limit: usize, mode: SizeMode) -> Vec<FileEntry> {
+        let mut files = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.is_dir {
+                stack.extend(node.children.iter());
+            } else if node.file_count > 0 {
+                files.push(node);
+            }
+        }
+        files.sort_by_key(|node| std::cmp::Reverse(node.size_in(mode)));
+        files.into_iter().take(limit).map(TreeNode::entry).collect()
+    }
+}
+
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Identifies the filesystem an entry lives on.
+#[cfg(unix)]
+pub fn device_id(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Identifies the inode behind an entry as a (device, inode) pair.
+#[cfg(unix)]
+pub fn inode_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn inode_key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Identifies the inode behind a regular file with more than one hard link,
+/// so the scan can charge its bytes only once.
+#[cfg(unix)]
+pub fn hard_link_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    if metadata.is_file() && metadata.nlink() > 1 {
+        inode_key(metadata)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn hard_link_key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// How the scan treats symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SymlinkPolicy {
+    /// List links but count nothing for them.
+    DontFollow,
+    /// Count what the link points to, descending into linked directories
+    /// unless that would loop back into one of their own ancestors.
+    Follow,
+    /// Count only the few bytes the link itself takes up, like `du`.
+    #[default]
+    LinkSizeOnly,
+}
+
+impl SymlinkPolicy {
+    pub const ALL: [SymlinkPolicy; 3] = [
+        SymlinkPolicy::DontFollow,
+        SymlinkPolicy::Follow,
+        SymlinkPolicy::LinkSizeOnly,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SymlinkPolicy::DontFollow => "Don't Follow",
+            SymlinkPolicy::Follow => "Follow",
+            SymlinkPolicy::LinkSizeOnly => "Link Size Only",
+        }
+    }
+}
+
+/// Knobs that control how a scan walks the filesystem.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanOptions {
+    /// Number of threads walking the tree. `1` walks on the calling thread.
+    pub threads: usize,
+    /// Don't descend into directories on a different filesystem than the
+    /// scan root; they are kept in the tree as skipped mount points.
+    pub one_file_system: bool,
+    /// Mount points to descend into even when `one_file_system` is set.
+    #[serde(with = "super::os_path::vec")]
+    pub include_mounts: Vec<PathBuf>,
+    pub symlinks: SymlinkPolicy,
+    pub exclude: ExcludeRules,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            one_file_system: true,
+            include_mounts: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            exclude: ExcludeRules::default(),
+        }
+    }
+}
+
+/// Where, when and how a scan was taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanInfo {
+    #[serde(with = "super::os_path")]
+    pub root: PathBuf,
+    pub scanned_at: SystemTime,
+    pub host: String,
+    pub options: ScanOptions,
+}
+
+impl ScanInfo {
+    pub fn new(root: &Path, options: &ScanOptions) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            scanned_at: SystemTime::now(),
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            options: options.clone(),
+        }
+    }
+}
+
+/// The outcome of a scan: the tree plus every entry that could not be read.
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub info: ScanInfo,
+    pub tree: TreeNode,
+    pub errors: Vec<ScanError>,
+    /// Directories that were listed from disk.
+    pub dirs_scanned: u64,
+    /// Directories whose listing was taken from a previous scan instead.
+    pub dirs_reused: u64,
+    /// The one link each multiply-linked file's bytes were charged to, by
+    /// (device, inode).
+    pub hard_links: HashMap<(u64, u64), PathBuf>,
+}
+
+/// Walks `path` once and builds the full directory tree below it,
+/// periodically publishing counters to `progress` so another thread can
+/// observe the scan. If `cancel` fires, the walk stops early and returns the
+/// totals accumulated so far with the unfinished nodes marked `partial`.
+///
+/// Only a root that can't be read at all is an error; anything unreadable
+/// below it is collected in `ScanResult::errors`.
+pub fn scan_tree(
+    path: &Path,
+    options: &ScanOptions,
+    progress: &Mutex<ScanProgress>,
+    cancel: &CancelToken,
+) -> Result<ScanResult, ScanError> {
+    rescan_tree(path, options, None, progress, cancel)
+}
+
+/// Like `scan_tree`, but reuses `previous`, an earlier scan of the same
+/// path with the same options. Directories whose inode and mtime are
+/// unchanged aren't listed again; their cached listing is used instead, but
+/// every entry in it is still looked at, so files rewritten in place are
+/// measured afresh.
+pub fn rescan_tree(
+    path: &Path,
+    options: &ScanOptions,
+    previous: Option<&TreeNode>,
+    progress: &Mutex<ScanProgress>,
+    cancel: &CancelToken,
+) -> Result<ScanResult, ScanError> {
+    let info = ScanInfo::new(path, options);
+    let metadata = fs::metadata(path).map_err(|e| ScanError::new(path, &e))?;
+    let root = TreeNode::leaf(path.to_path_buf(), &metadata);
+    if !root.is_dir {
+        return Ok(ScanResult {
+            info,
+            tree: root,
+            errors: Vec::new(),
+            dirs_scanned: 0,
+            dirs_reused: 0,
+            hard_links: HashMap::new(),
         });
     }
-    
-    entries
+    let previous = previous.filter(|previous| previous.path == path);
+    let walk = ParallelWalk::new(options, progress, cancel);
+    let (tree, errors) = walk.run(root, &metadata, previous);
+    let (dirs_scanned, dirs_reused) = walk.dir_counts();
+    let hard_links = walk.take_hard_links();
+    Ok(ScanResult { info, tree, errors, dirs_scanned, dirs_reused, hard_links })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Instant;
+    use walkdir::WalkDir;
+
+    /// Builds `width` directories per level, `depth` levels deep, each with
+    /// `files` small files of varying size, and returns its root.
+    pub(crate) fn fixture(name: &str, width: usize, depth: usize, files: usize) -> PathBuf {
+        fn fill(dir: &Path, width: usize, depth: usize, files: usize) {
+            fs::create_dir_all(dir).unwrap();
+            for i in 0..files {
+                fs::write(dir.join(format!("file{i}.bin")), vec![0u8; i * 37 % 4096]).unwrap();
+            }
+            if depth > 0 {
+                for i in 0..width {
+                    fill(&dir.join(format!("dir{i}")), width, depth - 1, files);
+                }
+            }
+        }
+
+        let root = std::env::temp_dir()
+            .join(format!("mac-space-explorer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fill(&root, width, depth, files);
+        root
+    }
+
+    pub(crate) fn scan(root: &Path, threads: usize) -> TreeNode {
+        scan_with(root, &ScanOptions { threads, ..ScanOptions::default() }).tree
+    }
+
+    pub(crate) fn scan_with(root: &Path, options: &ScanOptions) -> ScanResult {
+        let progress = Mutex::new(ScanProgress::default());
+        scan_tree(root, options, &progress, &CancelToken::new()).unwrap()
+    }
+
+    /// The plain `walkdir` sum the scanner used before it built a tree.
+    fn sequential_size(root: &Path) -> u64 {
+        WalkDir::new(root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    #[test]
+    fn parallel_walk_matches_sequential_totals() {
+        let root = fixture("totals", 3, 3, 5);
+        let expected = sequential_size(&root);
+
+        for threads in [1, 2, 8] {
+            let tree = scan(&root, threads);
+            assert_eq!(tree.size, expected, "threads = {threads}");
+            assert_eq!(tree.file_count, 5 * (1 + 3 + 9 + 27), "threads = {threads}");
+            assert!(!tree.partial);
+
+            let child = tree.find(&root.join("dir1").join("dir2")).unwrap();
+            assert_eq!(child.size, sequential_size(&child.path));
+            assert!(tree.allocated > 0);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_are_charged_once() {
+        let root = fixture("hardlinks", 0, 0, 0);
+        fs::write(root.join("original"), vec![1u8; 10_000]).unwrap();
+        fs::create_dir(root.join("copies")).unwrap();
+        fs::hard_link(root.join("original"), root.join("copies").join("link")).unwrap();
+
+        let tree = scan(&root, 2);
+        assert_eq!(tree.size, 10_000);
+        assert_eq!(tree.hardlinked, 20_000);
+        assert_eq!(tree.file_count, 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn directories_are_as_new_as_their_newest_file() {
+        let root = fixture("newest", 0, 0, 0);
+        let project = root.join("project");
+        let src = project.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("old.rs"), b"old").unwrap();
+        fs::write(src.join("new.rs"), b"new").unwrap();
+
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let new = old + Duration::from_secs(86_400);
+        let touch = |path: &Path, time| fs::File::open(path).unwrap().set_modified(time).unwrap();
+        touch(&src.join("new.rs"), new);
+        for path in [&src.join("old.rs"), &src, &project] {
+            touch(path, old);
+        }
+
+        let mut tree = scan(&root, 2);
+        let node = tree.find(&project).unwrap();
+        assert_eq!(node.modified, old);
+        assert_eq!(node.newest, new);
+        assert_eq!(node.entry().newest, new);
+
+        tree.remove(&src.join("new.rs"));
+        assert_eq!(tree.find(&project).unwrap().newest, old);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_policies() {
+        let root = fixture("symlinks", 1, 1, 1);
+        std::os::unix::fs::symlink(root.join("dir0"), root.join("dir0").join("loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("file0.bin"), root.join("alias")).unwrap();
+        let real_size = sequential_size(&root);
+
+        let scan_with = |symlinks| {
+            let progress = Mutex::new(ScanProgress::default());
+            let options = ScanOptions { symlinks, ..ScanOptions::default() };
+            scan_tree(&root, &options, &progress, &CancelToken::new()).unwrap().tree
+        };
+
+        let tree = scan_with(SymlinkPolicy::DontFollow);
+        assert_eq!(tree.size, real_size);
+        let alias = tree.find(&root.join("alias")).unwrap();
+        assert_eq!(alias.link_target.as_deref(), Some(root.join("file0.bin").as_path()));
+
+        let tree = scan_with(SymlinkPolicy::LinkSizeOnly);
+        let link_sizes = fs::symlink_metadata(root.join("alias")).unwrap().len()
+            + fs::symlink_metadata(root.join("dir0").join("loop")).unwrap().len();
+        assert_eq!(tree.size, real_size + link_sizes);
+
+        let tree = scan_with(SymlinkPolicy::Follow);
+        assert_eq!(tree.size, real_size * 2 - sequential_size(&root.join("dir0")));
+        let looped = tree.find(&root.join("dir0").join("loop")).unwrap();
+        assert!(looped.is_dir && looped.skipped);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_directories_are_reported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = fixture("errors", 2, 1, 1);
+        let locked = root.join("dir1");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root ignores permission bits, so there is nothing to report.
+        let readable_anyway = fs::read_dir(&locked).is_ok();
+
+        let progress = Mutex::new(ScanProgress::default());
+        let options = ScanOptions { threads: 2, ..ScanOptions::default() };
+        let result = scan_tree(&root, &options, &progress, &CancelToken::new()).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        if !readable_anyway {
+            assert_eq!(result.errors.len(), 1);
+            assert_eq!(result.errors[0].path, locked);
+            assert_eq!(result.errors[0].kind, ScanErrorKind::PermissionDenied);
+        }
+        let missing = scan_tree(&root.join("missing"), &options, &progress, &CancelToken::new());
+        assert_eq!(missing.unwrap_err().kind, ScanErrorKind::NotFound);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn excluded_bytes_go_to_their_own_bucket() {
+        let root = fixture("exclude", 2, 2, 2);
+        fs::write(root.join("dir0").join(".gitignore"), "dir1/\n").unwrap();
+        let total = sequential_size(&root);
+
+        let mut options = ScanOptions { threads: 2, ..ScanOptions::default() };
+        options.exclude.add("file1.bin");
+        options.exclude.add(&root.join("dir1").to_string_lossy());
+        options.exclude.use_ignore_files = true;
+
+        // Excluded directories aren't read unless asked to measure them.
+        let result = scan_with(&root, &options);
+        assert_eq!(result.dirs_scanned, 3);
+        let dir1 = result.tree.find(&root.join("dir1")).unwrap();
+        assert!(dir1.is_excluded && dir1.skipped && dir1.children.is_empty());
+        assert_eq!(dir1.excluded, 0);
+        assert!(result.tree.excludes_unmeasured());
+
+        options.exclude.measure = true;
+        let tree = scan_with(&root, &options).tree;
+        assert_eq!(tree.size + tree.excluded, total);
+        assert!(!tree.excludes_unmeasured());
+
+        let dir1 = tree.find(&root.join("dir1")).unwrap();
+        assert!(dir1.is_excluded && dir1.children.is_empty());
+        assert_eq!(dir1.excluded, sequential_size(&root.join("dir1")));
+        assert!(tree.find(&root.join("dir0").join("dir1")).unwrap().is_excluded);
+        assert!(!tree.find(&root.join("dir0").join("dir0")).unwrap().is_excluded);
+        assert!(tree.find(&root.join("file1.bin")).unwrap().is_excluded);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn cancelled_scan_is_partial() {
+        let root = fixture("cancel", 2, 2, 2);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        let progress = Mutex::new(ScanProgress::default());
+        let options = ScanOptions { threads: 2, ..ScanOptions::default() };
+        let tree = scan_tree(&root, &options, &progress, &cancel).unwrap().tree;
+        assert!(tree.partial);
+        assert_eq!(tree.size, 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rescan_only_lists_changed_directories() {
+        let root = fixture("rescan", 2, 2, 2);
+        let progress = Mutex::new(ScanProgress::default());
+        let options = ScanOptions::default();
+        let first = scan_tree(&root, &options, &progress, &CancelToken::new()).unwrap();
+        assert_eq!((first.dirs_scanned, first.dirs_reused), (7, 0));
+
+        fs::write(root.join("dir0").join("new.bin"), vec![0u8; 500]).unwrap();
+        fs::remove_file(root.join("dir1").join("dir0").join("file1.bin")).unwrap();
+        // Rewriting a file in place leaves its directory's listing as it was.
+        fs::write(root.join("dir1").join("dir1").join("file0.bin"), vec![0u8; 9999]).unwrap();
+        let second = rescan_tree(&root, &options, Some(&first.tree), &progress, &CancelToken::new()).unwrap();
+        assert_eq!((second.dirs_scanned, second.dirs_reused), (2, 5));
+        assert_eq!(second.tree.size, sequential_size(&root));
+        assert_eq!(second.tree.file_count, first.tree.file_count);
+
+        let mut tree = second.tree;
+        let removed = tree.remove(&root.join("dir0")).unwrap();
+        assert_eq!(tree.size, sequential_size(&root) - removed.size);
+        assert!(tree.find(&root.join("dir0")).is_none());
+        assert!(tree.remove(&root.join("missing")).is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Best of `runs` timings of `walk`.
+    fn best_of(runs: usize, mut walk: impl FnMut()) -> Duration {
+        (0..runs)
+            .map(|_| {
+                let started = Instant::now();
+                walk();
+                started.elapsed()
+            })
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_walk`.
+    /// Fails if, with more than one CPU, the parallel walk is no faster than
+    /// a single thread.
+    #[test]
+    #[ignore]
+    fn bench_walk() {
+        let root = fixture("bench", 8, 3, 20);
+        let expected = sequential_size(&root);
+
+        let walkdir = best_of(3, || assert_eq!(sequential_size(&root), expected));
+        println!("walkdir sum:       {:>8.1?}", walkdir);
+        let single = best_of(3, || assert_eq!(scan(&root, 1).size, expected));
+        println!("scan,  1 thread:   {:>8.1?}", single);
+
+        let cpus = ScanOptions::default().threads;
+        let mut parallel = single;
+        for threads in [2, 4, cpus] {
+            let elapsed = best_of(3, || assert_eq!(scan(&root, threads).size, expected));
+            println!(
+                "scan, {threads:>2} threads: {:>8.1?}  {:.2}x vs 1 thread, {:.2}x vs walkdir",
+                elapsed,
+                single.as_secs_f64() / elapsed.as_secs_f64(),
+                walkdir.as_secs_f64() / elapsed.as_secs_f64()
+            );
+            if threads == cpus {
+                parallel = elapsed;
+            }
+        }
+
+        if cpus > 1 {
+            assert!(parallel < single, "{cpus} threads took {parallel:?}, 1 thread {single:?}");
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
 }
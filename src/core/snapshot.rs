@@ -0,0 +1,259 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use super::os_path::OsText;
+use super::scanner::{ScanError, ScanInfo, TreeNode};
+
+/// File extension used for saved scans.
+pub const SNAPSHOT_EXTENSION: &str = "msesnap";
+
+const SNAPSHOT_FORMAT: &str = "mac-space-explorer-snapshot";
+
+/// Bumped whenever the on-disk layout changes incompatibly.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A saved scan that can be browsed without the scanned disk present.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub info: ScanInfo,
+    pub tree: TreeNode,
+    pub errors: Vec<ScanError>,
+}
+
+/// Gzip'd JSON layout of a snapshot. The tree is stored as a flat pre-order
+/// list of nodes that refer to their parent by index and only keep their
+/// own name, which keeps files small and avoids deeply nested JSON.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    format: String,
+    version: u32,
+    info: ScanInfo,
+    errors: Vec<ScanError>,
+    nodes: Vec<SnapshotNode>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotNode {
+    parent: Option<usize>,
+    name: OsText,
+    size: u64,
+    allocated: u64,
+    hardlinked: u64,
+    file_count: u64,
+    created: SystemTime,
+    modified: SystemTime,
+    is_dir: bool,
+    is_mount: bool,
+    #[serde(with = "super::os_path::option")]
+    link_target: Option<PathBuf>,
+    skipped: bool,
+    is_excluded: bool,
+    excluded: u64,
+    partial: bool,
+    #[serde(default)]
+    inode: u64,
+}
+
+impl SnapshotNode {
+    fn new(node: &TreeNode, parent: Option<usize>) -> io::Result<Self> {
+        let name = match parent {
+            Some(_) => OsText::new(node.path.file_name().unwrap_or_default())?,
+            None => OsText::Text(String::new()),
+        };
+        Ok(Self {
+            parent,
+            name,
+            size: node.size,
+            allocated: node.allocated,
+            hardlinked: node.hardlinked,
+            file_count: node.file_count,
+            created: node.created,
+            modified: node.modified,
+            is_dir: node.is_dir,
+            is_mount: node.is_mount,
+            link_target: node.link_target.clone(),
+            skipped: node.skipped,
+            is_excluded: node.is_excluded,
+            excluded: node.excluded,
+            partial: node.partial,
+            inode: node.inode,
+        })
+    }
+
+    fn into_node(self, path: PathBuf) -> TreeNode {
+        TreeNode {
+            path,
+            size: self.size,
+            allocated: self.allocated,
+            hardlinked: self.hardlinked,
+            file_count: self.file_count,
+            created: self.created,
+            modified: self.modified,
+            newest: self.modified,
+            is_dir: self.is_dir,
+            is_mount: self.is_mount,
+            link_target: self.link_target,
+            skipped: self.skipped,
+            is_excluded: self.is_excluded,
+            excluded: self.excluded,
+            partial: self.partial,
+            inode: self.inode,
+            children: Vec::new(),
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+pub fn save(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let mut nodes = Vec::new();
+    let mut stack = vec![(&snapshot.tree, None)];
+    while let Some((node, parent)) = stack.pop() {
+        let index = nodes.len();
+        nodes.push(SnapshotNode::new(node, parent)?);
+        stack.extend(node.children.iter().rev().map(|child| (child, Some(index))));
+    }
+
+    let file = SnapshotFile {
+        format: SNAPSHOT_FORMAT.to_string(),
+        version: SNAPSHOT_VERSION,
+        info: snapshot.info.clone(),
+        errors: snapshot.errors.clone(),
+        nodes,
+    };
+
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    serde_json::to_writer(&mut encoder, &file)?;
+    encoder.finish()?.flush()
+}
+
+pub fn load(path: &Path) -> io::Result<Snapshot> {
+    let decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+    let value: serde_json::Value = serde_json::from_reader(decoder)?;
+
+    if value["format"] != SNAPSHOT_FORMAT {
+        return Err(invalid(format!("{} is not a scan snapshot", path.display())));
+    }
+    let version = value["version"].as_u64().unwrap_or(0);
+    if version != u64::from(SNAPSHOT_VERSION) {
+        return Err(invalid(format!(
+            "snapshot version {} is not supported (expected {})",
+            version, SNAPSHOT_VERSION
+        )));
+    }
+    let file: SnapshotFile = serde_json::from_value(value)?;
+
+    // Parents always come before their children, so paths can be rebuilt
+    // front to back and children attached back to front, each one complete
+    // by the time it moves into its parent.
+    let mut slots: Vec<Option<(Option<usize>, TreeNode)>> = Vec::with_capacity(file.nodes.len());
+    for node in file.nodes {
+        let path = match node.parent {
+            None => file.info.root.clone(),
+            Some(parent) => match slots.get(parent) {
+                Some(Some((_, parent))) => parent.path.join(node.name.to_os_string()?),
+                _ => return Err(invalid("snapshot node refers to a missing parent")),
+            },
+        };
+        slots.push(Some((node.parent, node.into_node(path))));
+    }
+
+    for index in (1..slots.len()).rev() {
+        if let Some((Some(parent), child)) = slots[index].take() {
+            if let Some((_, parent)) = slots[parent].as_mut() {
+                parent.newest = parent.newest.max(child.newest);
+                parent.children.push(child);
+            }
+        }
+    }
+
+    let (_, mut tree) = slots.first_mut()
+        .and_then(Option::take)
+        .ok_or_else(|| invalid("snapshot has no root node"))?;
+    restore_order(&mut tree);
+
+    Ok(Snapshot { info: file.info, tree, errors: file.errors })
+}
+
+/// Children were attached last-first; put them back in their saved order.
+fn restore_order(node: &mut TreeNode) {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        node.children.reverse();
+        stack.extend(node.children.iter_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::scanner::tests::{fixture, scan_with};
+    use crate::core::scanner::ScanOptions;
+    use std::{ffi::OsStr, fs};
+
+    #[test]
+    fn snapshot_round_trip() {
+        let root = fixture("snapshot", 0, 0, 0);
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::write(root.join("a").join("b").join("deep.bin"), vec![0u8; 1234]).unwrap();
+        fs::write(root.join("top.bin"), vec![0u8; 99]).unwrap();
+
+        let result = scan_with(&root, &ScanOptions::default());
+        let file = root.join(format!("scan.{}", SNAPSHOT_EXTENSION));
+        let snapshot = Snapshot { info: result.info, tree: result.tree, errors: result.errors };
+        save(&file, &snapshot).unwrap();
+
+        let loaded = load(&file).unwrap();
+        assert_eq!(loaded.info.root, root);
+        assert_eq!(loaded.tree.size, snapshot.tree.size);
+        assert_eq!(loaded.tree.children.len(), snapshot.tree.children.len());
+        let deep = loaded.tree.find(&root.join("a").join("b").join("deep.bin")).unwrap();
+        assert_eq!(deep.size, 1234);
+        assert_eq!(deep.modified, snapshot.tree.find(&deep.path).unwrap().modified);
+        let dir = root.join("a");
+        assert_eq!(loaded.tree.find(&dir).unwrap().newest, snapshot.tree.find(&dir).unwrap().newest);
+
+        fs::write(&file, b"not a snapshot").unwrap();
+        assert!(load(&file).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_utf8_survive_the_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let base = fixture("snapshot-names", 0, 0, 0);
+        let name = OsStr::from_bytes(b"caf\xe9");
+        let root = base.join(name);
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join(name), vec![0u8; 42]).unwrap();
+        std::os::unix::fs::symlink(base.join(name).join(name), root.join("link")).unwrap();
+
+        let mut options = ScanOptions::default();
+        options.exclude.paths.push(root.join("gone").join(name));
+        let result = scan_with(&root, &options);
+        let file = base.join(format!("scan.{}", SNAPSHOT_EXTENSION));
+        let snapshot = Snapshot { info: result.info, tree: result.tree, errors: result.errors };
+        save(&file, &snapshot).unwrap();
+
+        let loaded = load(&file).unwrap();
+        assert_eq!(loaded.info.root, root);
+        assert_eq!(loaded.info.options.exclude.paths, options.exclude.paths);
+        assert_eq!(loaded.tree.find(&root.join(name)).unwrap().size, 42);
+        let link = loaded.tree.find(&root.join("link")).unwrap();
+        assert_eq!(link.link_target.as_deref(), Some(root.join(name).as_path()));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    collections::{hash_map, HashMap, VecDeque},
+    ffi::OsStr,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::exclude::{ExcludeMatcher, IgnoreChain};
+use super::scanner::{
+    device_id, hard_link_key, inode_key, CancelToken, ScanError, ScanOptions, ScanProgress,
+    SymlinkPolicy, TreeNode,
+};
+
+/// How often a running scan publishes its counters to the shared progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A directory waiting to be listed. `node` has no children yet; they are
+/// attached to it once the whole walk has finished.
+struct Job<'a> {
+    id: usize,
+    parent: Option<usize>,
+    /// Index of the top-level entry this directory lives under, used to
+    /// report how many of the root's children are done.
+    top: Option<usize>,
+    /// Filesystem the directory lives on, to spot mount points below it.
+    dev: Option<u64>,
+    /// Directories on the path from the root, only tracked when following
+    /// symlinks so links back into them can be caught.
+    ancestors: Option<Arc<Ancestor>>,
+    /// Ignore files in effect for the directory's entries.
+    ignores: Option<Arc<IgnoreChain>>,
+    /// Set inside an excluded subtree, which is only walked when measuring
+    /// excluded directories, and not matched against the rules again.
+    excluded: bool,
+    /// The directory as a previous scan saw it, if rescanning.
+    cached: Option<&'a TreeNode>,
+    node: TreeNode,
+}
+
+struct Ancestor {
+    key: Option<(u64, u64)>,
+    parent: Option<Arc<Ancestor>>,
+}
+
+impl Ancestor {
+    fn chain_contains(this: &Option<Arc<Ancestor>>, key: (u64, u64)) -> bool {
+        let mut current = this.as_deref();
+        while let Some(ancestor) = current {
+            if ancestor.key == Some(key) {
+                return true;
+            }
+            current = ancestor.parent.as_deref();
+        }
+        false
+    }
+}
+
+/// A listed directory with its files attached but not its subdirectories.
+struct Listed {
+    id: usize,
+    parent: Option<usize>,
+    node: TreeNode,
+}
+
+/// Work-stealing directory walker. Every worker owns a deque of pending
+/// directories: it pops its own newest job and, when empty, steals the
+/// oldest job from another worker, so big subtrees get spread across
+/// threads without any up-front partitioning.
+pub struct ParallelWalk<'a> {
+    queues: Vec<Mutex<VecDeque<Job<'a>>>>,
+    /// Jobs queued or being listed; the walk is done when this drops to 0.
+    pending: AtomicUsize,
+    /// Workers with nothing to steal sleep on `wake` until a job is pushed
+    /// or the walk is done. Both are signalled with `idle` held.
+    idle: Mutex<()>,
+    wake: Condvar,
+    next_id: AtomicUsize,
+    /// Outstanding directory count per top-level entry of the root.
+    tops: OnceLock<Vec<AtomicUsize>>,
+    /// (device, inode) of every multiply-linked file charged so far, and
+    /// the link it was charged to.
+    inodes: Mutex<HashMap<(u64, u64), PathBuf>>,
+    options: &'a ScanOptions,
+    exclude: ExcludeMatcher,
+    errors: Mutex<Vec<ScanError>>,
+    cancel: &'a CancelToken,
+    shared: &'a Mutex<ScanProgress>,
+    total_entries: AtomicUsize,
+    scanned_entries: AtomicUsize,
+    files_scanned: AtomicU64,
+    total_size: AtomicU64,
+    dirs_scanned: AtomicU64,
+    dirs_reused: AtomicU64,
+    started: Instant,
+    last_publish: Mutex<Instant>,
+}
+
+impl<'a> ParallelWalk<'a> {
+    pub fn new(options: &'a ScanOptions, progress: &'a Mutex<ScanProgress>, cancel: &'a CancelToken) -> Self {
+        Self {
+            queues: (0..options.threads.max(1)).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicUsize::new(0),
+            idle: Mutex::new(()),
+            wake: Condvar::new(),
+            next_id: AtomicUsize::new(0),
+            tops: OnceLock::new(),
+            inodes: Mutex::new(HashMap::new()),
+            options,
+            exclude: options.exclude.compile(),
+            errors: Mutex::new(Vec::new()),
+            cancel,
+            shared: progress,
+            total_entries: AtomicUsize::new(0),
+            scanned_entries: AtomicUsize::new(0),
+            files_scanned: AtomicU64::new(0),
+            total_size: AtomicU64::new(0),
+            dirs_scanned: AtomicU64::new(0),
+            dirs_reused: AtomicU64::new(0),
+            started: Instant::now(),
+            last_publish: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Walks everything below the directory `root`, described by `metadata`,
+    /// and returns it with the full tree attached, along with every entry
+    /// that could not be read. `cached` is an earlier scan of `root` whose
+    /// unchanged directories don't need listing again.
+    pub fn run(&self, root: TreeNode, metadata: &fs::Metadata, cached: Option<&'a TreeNode>) -> (TreeNode, Vec<ScanError>) {
+        let job = Job {
+            id: self.allocate_id(),
+            parent: None,
+            top: None,
+            dev: device_id(metadata),
+            ancestors: self.ancestors_for(None, metadata),
+            ignores: None,
+            excluded: false,
+            cached,
+            node: root,
+        };
+        self.push(0, job);
+
+        let listed: Vec<Listed> = thread::scope(|scope| {
+            let helpers: Vec<_> = (1..self.queues.len())
+                .map(|worker| scope.spawn(move || self.work(worker)))
+                .collect();
+            let mut listed = self.work(0);
+            for helper in helpers {
+                listed.extend(helper.join().unwrap_or_default());
+            }
+            listed
+        });
+
+        self.publish(None);
+        let mut errors = std::mem::take(&mut *self.errors.lock().unwrap());
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+        (assemble(listed), errors)
+    }
+
+    /// Directories listed from disk and directories reused from the cached
+    /// tree, in that order.
+    pub fn dir_counts(&self) -> (u64, u64) {
+        (self.dirs_scanned.load(Ordering::Relaxed), self.dirs_reused.load(Ordering::Relaxed))
+    }
+
+    /// Takes the link each multiply-linked inode was charged to.
+    pub fn take_hard_links(&self) -> HashMap<(u64, u64), PathBuf> {
+        std::mem::take(&mut *self.inodes.lock().unwrap())
+    }
+
+    fn record(&self, path: &std::path::Path, error: &std::io::Error) {
+        self.errors.lock().unwrap().push(ScanError::new(path, error));
+    }
+
+    /// Lists `path`, recording the directory itself or any entry in it that
+    /// can't be read.
+    fn read_entries(&self, path: &std::path::Path) -> Vec<PathBuf> {
+        match fs::read_dir(path) {
+            Ok(read_dir) => read_dir
+                .filter_map(|entry| entry.map_err(|e| self.record(path, &e)).ok())
+                .map(|entry| entry.path())
+                .collect(),
+            Err(e) => {
+                self.record(path, &e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// The entries of a directory whose listing is unchanged since `cached`
+    /// was scanned. Only the listing is reused: every entry is looked at
+    /// again, since files rewritten in place don't touch their directory.
+    fn cached_entries(&self, cached: &TreeNode) -> Vec<PathBuf> {
+        cached.children.iter().map(|child| child.path.clone()).collect()
+    }
+
+    fn ancestors_for(&self, parent: Option<&Arc<Ancestor>>, metadata: &fs::Metadata) -> Option<Arc<Ancestor>> {
+        (self.options.symlinks == SymlinkPolicy::Follow).then(|| {
+            Arc::new(Ancestor { key: inode_key(metadata), parent: parent.cloned() })
+        })
+    }
+
+    /// Builds the node for one directory entry, applying the symlink policy.
+    /// Returns it with the metadata its size was taken from.
+    fn entry_node(&self, path: PathBuf, ancestors: &Option<Arc<Ancestor>>) -> Option<(TreeNode, fs::Metadata)> {
+        let metadata = fs::symlink_metadata(&path)
+            .map_err(|e| self.record(&path, &e))
+            .ok()?;
+        let mut node = TreeNode::leaf(path.clone(), &metadata);
+        if !metadata.file_type().is_symlink() {
+            return Some((node, metadata));
+        }
+
+        let target = fs::read_link(&path)
+            .map_err(|e| self.record(&path, &e))
+            .unwrap_or_default();
+        let (mut node, metadata) = match self.options.symlinks {
+            SymlinkPolicy::DontFollow => {
+                node.allocated = 0;
+                (node, metadata)
+            }
+            SymlinkPolicy::LinkSizeOnly => {
+                node.size = metadata.len();
+                node.file_count = 1;
+                (node, metadata)
+            }
+            SymlinkPolicy::Follow => match fs::metadata(&path) {
+                Ok(target_metadata) => {
+                    let mut followed = TreeNode::leaf(path, &target_metadata);
+                    followed.skipped = followed.is_dir
+                        && inode_key(&target_metadata)
+                            .is_some_and(|key| Ancestor::chain_contains(ancestors, key));
+                    (followed, target_metadata)
+                }
+                // Dangling link: nothing to follow.
+                Err(_) => {
+                    node.allocated = 0;
+                    (node, metadata)
+                }
+            },
+        };
+        node.link_target = Some(target);
+        Some((node, metadata))
+    }
+
+    fn allocate_id(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn push(&self, worker: usize, job: Job<'a>) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queues[worker].lock().unwrap().push_back(job);
+        let _idle = self.idle.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    fn pop(&self, worker: usize) -> Option<Job<'a>> {
+        if let Some(job) = self.queues[worker].lock().unwrap().pop_back() {
+            return Some(job);
+        }
+        let count = self.queues.len();
+        (1..count)
+            .map(|offset| (worker + offset) % count)
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_front())
+    }
+
+    fn work(&self, worker: usize) -> Vec<Listed> {
+        let mut listed = Vec::new();
+        loop {
+            let Some(job) = self.pop(worker) else {
+                let idle = self.idle.lock().unwrap();
+                if self.pending.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                // Look again with `idle` held: a job pushed since can't
+                // signal until we are waiting, so its wakeup isn't lost.
+                if self.queues.iter().all(|queue| queue.lock().unwrap().is_empty()) {
+                    drop(self.wake.wait(idle).unwrap());
+                }
+                continue;
+            };
+            listed.push(self.list(worker, job));
+            if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                let _idle = self.idle.lock().unwrap();
+                self.wake.notify_all();
+            }
+        }
+        listed
+    }
+
+    /// Lists one directory: files become children straight away, while
+    /// subdirectories are queued as new jobs.
+    fn list(&self, worker: usize, job: Job<'a>) -> Listed {
+        let Job { id, parent, top, dev, ancestors, ignores, excluded, cached, mut node } = job;
+
+        if self.cancel.is_cancelled() {
+            node.partial = true;
+        } else {
+            let entries = match cached.filter(|cached| cached.listing_unchanged(&node)) {
+                Some(cached) => {
+                    self.dirs_reused.fetch_add(1, Ordering::Relaxed);
+                    self.cached_entries(cached)
+                }
+                None => {
+                    self.dirs_scanned.fetch_add(1, Ordering::Relaxed);
+                    self.read_entries(&node.path)
+                }
+            };
+            let cached_children: HashMap<&OsStr, &'a TreeNode> = cached
+                .map(|cached| {
+                    cached.children.iter()
+                        .filter(|child| child.is_dir)
+                        .filter_map(|child| Some((child.path.file_name()?, child)))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let ignores = if excluded { None } else { self.exclude.ignores_for(&node.path, &ignores) };
+            let is_root = parent.is_none();
+            if is_root {
+                self.total_entries.store(entries.len(), Ordering::Relaxed);
+                let _ = self.tops.set((0..entries.len()).map(|_| AtomicUsize::new(0)).collect());
+            }
+
+            for (index, path) in entries.into_iter().enumerate() {
+                if self.cancel.is_cancelled() {
+                    node.partial = true;
+                    break;
+                }
+
+                let Some((mut child, metadata)) = self.entry_node(path, &ancestors) else {
+                    if is_root {
+                        self.scanned_entries.fetch_add(1, Ordering::Relaxed);
+                    }
+                    continue;
+                };
+
+                if let Some(key) = hard_link_key(&metadata) {
+                    child.hardlinked = child.size;
+                    match self.inodes.lock().unwrap().entry(key) {
+                        hash_map::Entry::Vacant(entry) => {
+                            entry.insert(child.path.clone());
+                        }
+                        hash_map::Entry::Occupied(_) => {
+                            child.size = 0;
+                            child.allocated = 0;
+                        }
+                    }
+                }
+
+                let child_dev = device_id(&metadata);
+                if child.is_dir && dev.is_some() && child_dev != dev {
+                    child.is_mount = true;
+                    child.skipped = self.options.one_file_system
+                        && !self.options.include_mounts.contains(&child.path);
+                }
+
+                child.is_excluded = !excluded
+                    && self.exclude.is_excluded(&child.path, child.is_dir, &ignores);
+                if child.is_excluded && child.is_dir && !self.options.exclude.measure {
+                    child.skipped = true;
+                }
+
+                if child.is_dir && !child.skipped {
+                    let child_top = if is_root { Some(index) } else { top };
+                    if let (Some(t), Some(tops)) = (child_top, self.tops.get()) {
+                        tops[t].fetch_add(1, Ordering::SeqCst);
+                    }
+                    let cached = child.path.file_name()
+                        .and_then(|name| cached_children.get(name))
+                        .copied();
+                    self.push(worker, Job {
+                        id: self.allocate_id(),
+                        parent: Some(id),
+                        top: child_top,
+                        dev: child_dev,
+                        ancestors: self.ancestors_for(ancestors.as_ref(), &metadata),
+                        ignores: ignores.clone(),
+                        excluded: excluded || child.is_excluded,
+                        cached,
+                        node: child,
+                    });
+                    continue;
+                }
+
+                if child.file_count > 0 {
+                    self.files_scanned.fetch_add(1, Ordering::Relaxed);
+                    self.total_size.fetch_add(child.size, Ordering::Relaxed);
+                }
+                if is_root {
+                    self.scanned_entries.fetch_add(1, Ordering::Relaxed);
+                }
+                if child.is_excluded {
+                    child.exclude();
+                }
+                node.size += child.size;
+                node.allocated += child.allocated;
+                node.hardlinked += child.hardlinked;
+                node.excluded += child.excluded;
+                node.file_count += child.file_count;
+                node.newest = node.newest.max(child.newest);
+                node.children.push(child);
+            }
+        }
+
+        if let (Some(t), Some(tops)) = (top, self.tops.get()) {
+            if tops[t].fetch_sub(1, Ordering::SeqCst) == 1 {
+                self.scanned_entries.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.maybe_publish(&node.path);
+
+        Listed { id, parent, node }
+    }
+
+    fn maybe_publish(&self, current: &std::path::Path) {
+        let due = self.last_publish.try_lock().is_ok_and(|mut last| {
+            if last.elapsed() >= PROGRESS_INTERVAL {
+                *last = Instant::now();
+                true
+            } else {
+                false
+            }
+        });
+        if due {
+            self.publish(Some(current.to_path_buf()));
+        }
+    }
+
+    fn publish(&self, current_path: Option<PathBuf>) {
+        let snapshot = ScanProgress {
+            total_entries: self.total_entries.load(Ordering::Relaxed),
+            scanned_entries: self.scanned_entries.load(Ordering::Relaxed),
+            files_scanned: self.files_scanned.load(Ordering::Relaxed),
+            current_path,
+            total_size: self.total_size.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        };
+        if let Ok(mut shared) = self.shared.lock() {
+            *shared = snapshot;
+        }
+    }
+}
+
+/// Stitches listed directories back into a tree. Job ids are handed out
+/// when a directory is queued, so a child's id is always greater than its
+/// parent's and walking ids downwards finishes every child first.
+fn assemble(listed: Vec<Listed>) -> TreeNode {
+    let mut slots: Vec<Option<(Option<usize>, TreeNode)>> = Vec::new();
+    slots.resize_with(listed.len(), || None);
+    for Listed { id, parent, node } in listed {
+        slots[id] = Some((parent, node));
+    }
+
+    for id in (1..slots.len()).rev() {
+        let Some((Some(parent), mut child)) = slots[id].take() else {
+            continue;
+        };
+        if child.is_excluded {
+            child.exclude();
+        }
+        if let Some((_, parent)) = slots[parent].as_mut() {
+            parent.size += child.size;
+            parent.allocated += child.allocated;
+            parent.hardlinked += child.hardlinked;
+            parent.excluded += child.excluded;
+            parent.file_count += child.file_count;
+            parent.newest = parent.newest.max(child.newest);
+            parent.partial |= child.partial;
+            parent.children.push(child);
+        }
+    }
+
+    let (_, root) = slots[0].take().expect("the root directory is always listed");
+    root
+}
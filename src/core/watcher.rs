@@ -0,0 +1,466 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs, io, mem,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::scanner::{hard_link_key, rescan_tree, CancelToken, ScanOptions, ScanProgress, TreeNode};
+
+/// How long a burst of changes has to go quiet before it is reported.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Longest a continuous burst is held back before it is reported anyway.
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// How often a waiting watcher checks whether it was cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A platform's change notifications. Backends only say which paths
+/// changed; `Watcher::next_changes` looks at them again to find out how.
+pub trait WatchBackend: Send {
+    /// Waits up to `timeout` for changes and returns the paths they name,
+    /// which may be none.
+    fn wait(&mut self, timeout: Duration) -> io::Result<Vec<PathBuf>>;
+}
+
+/// A change to the scanned tree, worked out away from the UI thread so
+/// applying it is cheap.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Removed(PathBuf),
+    /// A file looked at again or a new directory scanned in full.
+    Updated(TreeNode),
+}
+
+/// Watches the directories of a scanned tree and reports changes in
+/// debounced batches. The watches themselves are only set up by `start`,
+/// so creating a watcher is cheap enough for the UI thread.
+pub struct Watcher {
+    root: PathBuf,
+    options: ScanOptions,
+    /// Every directory in the tree, so only new ones are scanned.
+    known: HashSet<PathBuf>,
+    /// The link each multiply-linked inode is charged to, so changes to its
+    /// other links don't count its bytes again.
+    hard_links: HashMap<(u64, u64), PathBuf>,
+    /// Directories still to be watched once the backend starts.
+    pending: Vec<PathBuf>,
+    backend: Option<Box<dyn WatchBackend>>,
+}
+
+impl Watcher {
+    /// Prepares to watch every directory of `tree` the scan descended into.
+    /// `hard_links` comes from the scan's result.
+    pub fn new(tree: &TreeNode, options: ScanOptions, hard_links: HashMap<(u64, u64), PathBuf>) -> Self {
+        let mut known = HashSet::new();
+        let mut pending = Vec::new();
+        let mut stack = vec![tree];
+        while let Some(node) = stack.pop() {
+            if !node.is_dir {
+                continue;
+            }
+            known.insert(node.path.clone());
+            if !node.skipped && !node.is_excluded {
+                pending.push(node.path.clone());
+                stack.extend(node.children.iter());
+            }
+        }
+        Self { root: tree.path.clone(), options, known, hard_links, pending, backend: None }
+    }
+
+    /// Adds the watches, one per directory, unless they are already in
+    /// place. `next_batch` starts the watcher if this wasn't called.
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.backend.is_none() {
+            self.backend = Some(platform_backend(&mem::take(&mut self.pending))?);
+        }
+        Ok(())
+    }
+
+    /// Blocks until a burst of changes has settled and returns the changed
+    /// paths, sorted and without duplicates. Returns nothing once `cancel`
+    /// fires.
+    pub fn next_batch(&mut self, cancel: &CancelToken) -> io::Result<Vec<PathBuf>> {
+        self.start()?;
+        let backend = self.backend.as_mut().unwrap();
+        let mut changed = BTreeSet::new();
+        let mut first: Option<Instant> = None;
+        while !cancel.is_cancelled() {
+            let timeout = match first {
+                None => POLL_INTERVAL,
+                Some(first) => DEBOUNCE.min(MAX_DELAY.saturating_sub(first.elapsed())),
+            };
+            let paths = backend.wait(timeout)?;
+            if paths.is_empty() {
+                if first.is_some() {
+                    break;
+                }
+                continue;
+            }
+            let first = *first.get_or_insert_with(Instant::now);
+            changed.extend(paths);
+            if first.elapsed() >= MAX_DELAY {
+                break;
+            }
+        }
+        if cancel.is_cancelled() {
+            return Ok(Vec::new());
+        }
+        Ok(changed.into_iter().collect())
+    }
+
+    /// Waits for the next batch and works out what it changed: entries
+    /// that are gone are removed, files are looked at again and new
+    /// directories are scanned. Symlinks are left for the next scan.
+    pub fn next_changes(&mut self, cancel: &CancelToken) -> io::Result<Vec<Change>> {
+        let paths = self.next_batch(cancel)?;
+        Ok(self.changes(&paths))
+    }
+
+    fn changes(&mut self, paths: &[PathBuf]) -> Vec<Change> {
+        let exclude = self.options.exclude.compile();
+        let mut changes = Vec::new();
+        let mut paths = paths.to_vec();
+        let mut next = 0;
+        while let Some(path) = paths.get(next).cloned() {
+            next += 1;
+            let path = &path;
+            if *path == self.root || !path.starts_with(&self.root) {
+                continue;
+            }
+            match fs::symlink_metadata(path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    if self.known.remove(path) {
+                        self.known.retain(|dir| !dir.starts_with(path));
+                    }
+                    self.hard_links.retain(|_, charged| !charged.starts_with(path));
+                    changes.push(Change::Removed(path.clone()));
+                }
+                Err(_) => {}
+                Ok(metadata) if metadata.file_type().is_symlink() => {}
+                Ok(metadata) if metadata.is_dir() => {
+                    if self.known.contains(path) {
+                        continue;
+                    }
+                    let progress = Mutex::new(ScanProgress::default());
+                    if let Ok(result) = rescan_tree(path, &self.options, None, &progress, &CancelToken::new()) {
+                        let mut node = result.tree;
+                        // The new directory was scanned on its own, so its
+                        // links may already be charged elsewhere in the tree.
+                        for (key, charged) in result.hard_links {
+                            if !self.charge(key, &charged) {
+                                if let Some(mut link) = node.remove(&charged) {
+                                    link.size = 0;
+                                    link.allocated = 0;
+                                    node.insert(link);
+                                }
+                            }
+                        }
+                        if exclude.is_excluded(path, true, &None) {
+                            node.exclude();
+                        }
+                        self.remember(&node);
+                        changes.push(Change::Updated(node));
+                    }
+                }
+                Ok(metadata) => {
+                    let mut node = TreeNode::leaf(path.clone(), &metadata);
+                    if let Some(key) = hard_link_key(&metadata) {
+                        node.hardlinked = node.size;
+                        if !self.charge(key, path) {
+                            node.size = 0;
+                            node.allocated = 0;
+                            // Writing through this link changed the bytes
+                            // charged to the other one.
+                            let charged = self.hard_links[&key].clone();
+                            if !paths.contains(&charged) {
+                                paths.push(charged);
+                            }
+                        }
+                    }
+                    if exclude.is_excluded(path, false, &None) {
+                        node.exclude();
+                    }
+                    changes.push(Change::Updated(node));
+                }
+            }
+        }
+        changes
+    }
+
+    /// Charges the inode `key` to the link at `path` unless another link
+    /// already carries it. Returns whether `path` carries its bytes.
+    fn charge(&mut self, key: (u64, u64), path: &Path) -> bool {
+        self.hard_links.entry(key).or_insert_with(|| path.to_path_buf()) == path
+    }
+
+    fn remember(&mut self, node: &TreeNode) {
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if node.is_dir {
+                self.known.insert(node.path.clone());
+                stack.extend(node.children.iter());
+            }
+        }
+    }
+}
+
+/// Patches `tree` with changes from `Watcher::next_changes`. Returns
+/// whether the tree changed.
+pub fn apply_changes(tree: &mut TreeNode, changes: Vec<Change>) -> bool {
+    let mut changed = false;
+    for change in changes {
+        changed |= match change {
+            Change::Removed(path) => tree.remove(&path).is_some(),
+            Change::Updated(node) => tree.insert(node),
+        };
+    }
+    changed
+}
+
+#[cfg(target_os = "linux")]
+fn platform_backend(dirs: &[PathBuf]) -> io::Result<Box<dyn WatchBackend>> {
+    Ok(Box::new(inotify::Inotify::new(dirs)?))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn platform_backend(_dirs: &[PathBuf]) -> io::Result<Box<dyn WatchBackend>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "live updates are not supported on this platform yet"))
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        collections::HashMap,
+        ffi::{CString, OsStr},
+        fs, io,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use super::WatchBackend;
+
+    const MASK: u32 = libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MODIFY
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_ONLYDIR;
+
+    /// Size of `struct inotify_event` without its trailing name.
+    const HEADER: usize = 16;
+
+    /// One inotify instance with a watch on every directory.
+    pub struct Inotify {
+        fd: i32,
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl Inotify {
+        pub fn new(dirs: &[PathBuf]) -> io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut inotify = Self { fd, dirs: HashMap::new() };
+            for dir in dirs {
+                inotify.add(dir)?;
+            }
+            Ok(inotify)
+        }
+
+        /// Watches `dir`. Only running out of watches is an error; a
+        /// directory that vanished or can't be read is simply not watched.
+        fn add(&mut self, dir: &Path) -> io::Result<()> {
+            let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+                return Ok(());
+            };
+            let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), MASK) };
+            if wd < 0 {
+                let error = io::Error::last_os_error();
+                return match error.raw_os_error() {
+                    Some(libc::ENOSPC) => Err(io::Error::new(
+                        error.kind(),
+                        "too many directories to watch (see fs.inotify.max_user_watches)",
+                    )),
+                    _ => Ok(()),
+                };
+            }
+            self.dirs.insert(wd, dir.to_path_buf());
+            Ok(())
+        }
+
+        /// Watches a directory that appeared after the scan and everything
+        /// below it. Directories moved within the tree keep their watch,
+        /// which is re-pointed at the new path.
+        fn add_tree(&mut self, dir: &Path) {
+            let mut stack = vec![dir.to_path_buf()];
+            while let Some(dir) = stack.pop() {
+                if self.add(&dir).is_err() {
+                    return;
+                }
+                if let Ok(entries) = fs::read_dir(&dir) {
+                    stack.extend(entries
+                        .filter_map(Result::ok)
+                        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+                        .map(|entry| entry.path()));
+                }
+            }
+        }
+    }
+
+    fn field(buffer: &[u8], at: usize) -> u32 {
+        u32::from_ne_bytes([buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]])
+    }
+
+    impl WatchBackend for Inotify {
+        fn wait(&mut self, timeout: Duration) -> io::Result<Vec<PathBuf>> {
+            let mut poll = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+            let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+            if unsafe { libc::poll(&mut poll, 1, timeout) } < 0 {
+                let error = io::Error::last_os_error();
+                return match error.kind() {
+                    io::ErrorKind::Interrupted => Ok(Vec::new()),
+                    _ => Err(error),
+                };
+            }
+
+            let mut paths = Vec::new();
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let read = unsafe { libc::read(self.fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+                if read <= 0 {
+                    let error = io::Error::last_os_error();
+                    match error.kind() {
+                        _ if read == 0 => break,
+                        io::ErrorKind::WouldBlock => break,
+                        io::ErrorKind::Interrupted => continue,
+                        _ => return Err(error),
+                    }
+                }
+
+                let read = read as usize;
+                let mut offset = 0;
+                while offset + HEADER <= read {
+                    let wd = field(&buffer, offset) as i32;
+                    let mask = field(&buffer, offset + 4);
+                    let len = field(&buffer, offset + 12) as usize;
+                    let name = &buffer[offset + HEADER..offset + HEADER + len];
+                    let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(len)];
+                    offset += HEADER + len;
+
+                    // The kernel dropped events, so the tree can no longer
+                    // be kept in step.
+                    if mask & libc::IN_Q_OVERFLOW != 0 {
+                        return Err(io::Error::other("too many changes to follow; rescan to catch up"));
+                    }
+                    if mask & libc::IN_IGNORED != 0 {
+                        self.dirs.remove(&wd);
+                        continue;
+                    }
+                    let Some(dir) = self.dirs.get(&wd) else {
+                        continue;
+                    };
+                    let path = if name.is_empty() { dir.clone() } else { dir.join(OsStr::from_bytes(name)) };
+                    if mask & libc::IN_ISDIR != 0 && mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                        self.add_tree(&path);
+                    }
+                    paths.push(path);
+                }
+            }
+            Ok(paths)
+        }
+    }
+
+    impl Drop for Inotify {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::core::scanner::tests::{fixture, scan_with};
+    use std::io::Write;
+
+    #[test]
+    fn watched_changes_patch_the_tree() {
+        let root = fixture("watch", 0, 0, 0);
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::write(root.join("logs").join("old.log"), vec![0u8; 100]).unwrap();
+
+        let options = ScanOptions::default();
+        let result = scan_with(&root, &options);
+        let mut tree = result.tree;
+        let mut watcher = Watcher::new(&tree, options.clone(), result.hard_links);
+        watcher.start().unwrap();
+
+        fs::write(root.join("logs").join("old.log"), vec![0u8; 2000]).unwrap();
+        fs::create_dir_all(root.join("downloads").join("nested")).unwrap();
+        fs::write(root.join("downloads").join("nested").join("file.iso"), vec![0u8; 5000]).unwrap();
+        fs::write(root.join("top.bin"), vec![0u8; 300]).unwrap();
+        fs::remove_file(root.join("top.bin")).unwrap();
+
+        let changed = watcher.next_changes(&CancelToken::new()).unwrap();
+        assert!(changed.iter().any(|change| matches!(change, Change::Updated(node) if node.path == root.join("downloads"))));
+        assert!(apply_changes(&mut tree, changed));
+        assert_eq!(tree.size, 2000 + 5000);
+        assert_eq!(tree.file_count, 2);
+        assert!(tree.find(&root.join("top.bin")).is_none());
+        assert_eq!(tree.find(&root.join("downloads")).unwrap().size, 5000);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn watched_hard_links_are_charged_once() {
+        let root = fixture("watch-links", 0, 0, 0);
+        fs::create_dir(root.join("copies")).unwrap();
+        fs::write(root.join("original"), vec![0u8; 1000]).unwrap();
+        fs::hard_link(root.join("original"), root.join("copies").join("link")).unwrap();
+
+        let options = ScanOptions::default();
+        let result = scan_with(&root, &options);
+        let mut tree = result.tree;
+        let mut watcher = Watcher::new(&tree, options.clone(), result.hard_links);
+        watcher.start().unwrap();
+
+        fs::OpenOptions::new().append(true).open(root.join("copies").join("link")).unwrap()
+            .write_all(&[0u8; 500]).unwrap();
+        fs::create_dir(root.join("more")).unwrap();
+        fs::hard_link(root.join("original"), root.join("more").join("link")).unwrap();
+
+        let changed = watcher.next_changes(&CancelToken::new()).unwrap();
+        assert!(apply_changes(&mut tree, changed));
+        assert_eq!(tree.size, 1500);
+        assert_eq!(tree.find(&root.join("more").join("link")).unwrap().size, 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn dropped_events_stop_the_watch() {
+        let root = fixture("watch-overflow", 0, 0, 0);
+        let options = ScanOptions::default();
+        let result = scan_with(&root, &options);
+        let mut watcher = Watcher::new(&result.tree, options, result.hard_links);
+        watcher.start().unwrap();
+
+        let queued: usize = fs::read_to_string("/proc/sys/fs/inotify/max_queued_events")
+            .map_or(16384, |max| max.trim().parse().unwrap());
+        for i in 0..=queued {
+            fs::File::create(root.join(i.to_string())).unwrap();
+        }
+        assert!(watcher.next_batch(&CancelToken::new()).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,23 +1,43 @@
+mod cli;
 mod core;
 mod ui;
 
 use iced::{
     widget::{
-        button, canvas, container, text,
+        button, canvas, checkbox, container, pick_list, progress_bar, radio, scrollable, text,
+        text_input,
         column, row,
     },
-    Application, Command, Element, Length, Rectangle, Settings,
-    Color, Theme, theme, Subscription, time,
+    futures::SinkExt,
+    subscription, Application, Command, Element, Length, Settings,
+    Color, Theme, theme, Subscription,
 };
 
 use native_dialog::{FileDialog, MessageDialog, MessageType};
 use thousands::Separable;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use crate::core::scanner::{FileEntry, scan_directory, ScanProgress};
-use crate::ui::treemap::TreeMap;
+use crate::core::category::{self, Category};
+use crate::core::diff::{diff_trees, DiffNode};
+use crate::core::export::{self, ExportFormat};
+use crate::core::import;
+use crate::core::scanner::{
+    CancelToken, FileEntry, rescan_tree, scan_tree, ScanError, ScanErrorKind, ScanInfo, ScanOptions,
+    ScanProgress, ScanResult, SizeMode, SymlinkPolicy, TreeNode,
+};
+use crate::core::snapshot::{self, Snapshot, SNAPSHOT_EXTENSION};
+use crate::core::watcher::{apply_changes, Change, Watcher};
+use crate::ui::treemap::{
+    age_color, category_color, delta_text, depth_color, parse_age_breakpoints, size_color, ColorScale, TreeMap,
+    DEFAULT_AGE_BREAKPOINTS, MOUNT_COLOR, SYMLINK_COLOR,
+};
+
+/// Deepest nesting the treemap can be set to.
+const MAX_TREEMAP_DEPTH: usize = 6;
 
 lazy_static::lazy_static! {
     pub static ref SELECTED_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
@@ -28,9 +48,39 @@ pub enum Message {
     SelectFolder,
     FolderSelected(Option<PathBuf>),
     Scan,
+    CancelScan,
     ScanProgress(ScanProgress),
-    ScanComplete(u64),
+    ScanComplete(Result<Box<ScanResult>, ScanError>),
+    FsChanged(Vec<Change>),
+    WatchFailed(String),
+    ToggleErrors,
+    ToggleLargestFiles,
+    /// Lists the entries of a treemap cell too small to draw one by one.
+    ShowSmallItems(Vec<FileEntry>),
+    CloseSmallItems,
+    SaveScan,
+    OpenScan,
+    ImportScan,
+    SetExportFormat(ExportFormat),
+    Export,
+    CompareWithScan,
+    ExitDiff,
     Select(Option<PathBuf>),
+    ToggleSizeMode,
+    SetTreemapDepth(usize),
+    SetColorScale(ColorScale),
+    ToggleSettings,
+    SetOneFileSystem(bool),
+    SetSymlinkPolicy(SymlinkPolicy),
+    SetThreads(usize),
+    ExcludeInputChanged(String),
+    AgeBreakpointsChanged(String),
+    AddExclude,
+    RemoveExcludePattern(usize),
+    RemoveExcludePath(usize),
+    SetUseIgnoreFiles(bool),
+    SetMeasureExcluded(bool),
+    IncludeMount(PathBuf),
     DrillDown,
     DrillUp,
     OpenInFinder,
@@ -45,12 +95,49 @@ pub struct SpaceExplorer {
     root_path: PathBuf,
     initial_root_path: PathBuf,
     treemap: TreeMap,
+    tree: Option<TreeNode>,
+    scan_info: Option<ScanInfo>,
+    /// Snapshot file the current tree was loaded from, if any.
+    snapshot_path: Option<PathBuf>,
+    scan_errors: Vec<ScanError>,
+    show_errors: bool,
+    show_largest_files: bool,
+    /// Entries grouped into a treemap cell the user asked to see listed.
+    small_items: Option<Vec<FileEntry>>,
     total_size: u64,
-    filter_age: Option<u64>,
-    filter_size: Option<u64>,
+    /// Set when excluded directories below `root_path` weren't read, so the
+    /// excluded total leaves them out.
+    excluded_unmeasured: bool,
     scan_progress: Option<ScanProgress>,
     scanning: bool,
+    scan_id: u64,
+    scan_cancel: CancelToken,
+    /// Earlier tree of the same root the running scan reuses.
+    scan_previous: Option<Arc<TreeNode>>,
+    /// Directories listed and reused by the last scan.
+    scan_dirs: Option<(u64, u64)>,
+    scan_options: ScanOptions,
+    /// Keeps the tree of the last live scan current while no scan runs.
+    watcher: Option<Arc<Mutex<Watcher>>>,
+    watch_cancel: CancelToken,
+    watch_error: Option<String>,
+    show_settings: bool,
+    exclude_input: String,
+    size_mode: SizeMode,
+    /// Directory levels drawn nested in the treemap.
+    treemap_depth: usize,
+    color_scale: ColorScale,
+    /// Ages in days splitting the age color scale, and what was typed for
+    /// them, which may not parse yet.
+    age_breakpoints: Vec<u64>,
+    age_input: String,
+    export_format: ExportFormat,
     largest_files: Vec<FileEntry>,
+    /// How much of the current directory each file category takes up.
+    category_totals: Vec<(Category, u64)>,
+    /// Older snapshot the current tree is being compared against.
+    diff_base: Option<Snapshot>,
+    diff: Option<DiffNode>,
 }
 
 impl Application for SpaceExplorer {
@@ -64,14 +151,39 @@ impl Application for SpaceExplorer {
         (
             SpaceExplorer {
                 root_path: home.clone(),
-                initial_root_path: home.clone(),
-                treemap: TreeMap::new(home),
+                initial_root_path: home,
+                treemap: TreeMap::new(),
+                tree: None,
+                scan_info: None,
+                snapshot_path: None,
+                scan_errors: Vec::new(),
+                show_errors: false,
+                show_largest_files: true,
+                small_items: None,
                 total_size: 0,
-                filter_age: None,
-                filter_size: None,
+                excluded_unmeasured: false,
                 scan_progress: None,
                 scanning: false,
+                scan_id: 0,
+                scan_cancel: CancelToken::new(),
+                scan_previous: None,
+                scan_dirs: None,
+                scan_options: ScanOptions::default(),
+                watcher: None,
+                watch_cancel: CancelToken::new(),
+                watch_error: None,
+                show_settings: false,
+                exclude_input: String::new(),
+                size_mode: SizeMode::default(),
+                treemap_depth: 3,
+                color_scale: ColorScale::default(),
+                age_breakpoints: DEFAULT_AGE_BREAKPOINTS.to_vec(),
+                age_input: DEFAULT_AGE_BREAKPOINTS.map(|days| days.to_string()).join(", "),
+                export_format: ExportFormat::default(),
                 largest_files: Vec::new(),
+                category_totals: Vec::new(),
+                diff_base: None,
+                diff: None,
             },
             Command::none(),
         )
@@ -83,7 +195,15 @@ impl Application for SpaceExplorer {
 
     fn subscription(&self) -> Subscription<Message> {
         if self.scanning {
-            time::every(Duration::from_millis(100)).map(|_| Message::Tick)
+            scan_subscription(
+                self.scan_id,
+                self.initial_root_path.clone(),
+                self.scan_options.clone(),
+                self.scan_previous.clone(),
+                self.scan_cancel.clone(),
+            )
+        } else if let Some(watcher) = &self.watcher {
+            watch_subscription(self.scan_id, watcher.clone(), self.watch_cancel.clone())
         } else {
             Subscription::none()
         }
@@ -98,67 +218,233 @@ impl Application for SpaceExplorer {
                 {
                     self.root_path = path.clone();
                     self.initial_root_path = path;
-                    self.treemap = TreeMap::new(self.root_path.clone());
+                    self.treemap = TreeMap::new();
                     return Command::perform(async {}, |_| Message::Scan);
                 }
                 Command::none()
             }
             Message::FolderSelected(_) => Command::none(),
             Message::Scan => {
-                if self.root_path.exists() {
+                if self.initial_root_path.exists() {
+                    self.show_settings = false;
+                    // A new id restarts the subscription even if a scan is
+                    // already running; stop the old walk so it doesn't linger.
+                    self.scan_cancel.cancel();
+                    self.scan_cancel = CancelToken::new();
+                    self.stop_watching();
+                    self.scan_id += 1;
+                    // The old tree is handed over to the walker rather than
+                    // copied; the progress screen doesn't need it. A scan
+                    // restarted midway keeps the tree it was handed.
+                    let reusable = |tree: &TreeNode| {
+                        tree.path == self.initial_root_path
+                            && self.scan_info.as_ref().is_some_and(|info| info.options == self.scan_options)
+                    };
+                    if self.tree.as_ref().is_some_and(reusable) {
+                        self.scan_previous = self.tree.take().map(Arc::new);
+                    } else if !self.scan_previous.as_deref().is_some_and(reusable) {
+                        self.scan_previous = None;
+                    }
                     self.scanning = true;
                     self.scan_progress = Some(ScanProgress::default());
-                    let mut progress = ScanProgress::default();
-                    let entries = scan_directory(&self.root_path, &mut progress);
-                    
-                    // Find largest files (only regular files, not directories)
-                    let mut all_files: Vec<_> = entries.iter()
-                        .filter(|e| !e.is_dir)
-                        .cloned()
-                        .collect();
-                    all_files.sort_by(|a, b| b.size.cmp(&a.size));
-                    self.largest_files = all_files.into_iter().take(10).collect();
-                    println!("Found {} largest files in {}", self.largest_files.len(), self.root_path.display());
-                    
-                    for (i, file) in self.largest_files.iter().enumerate() {
-                        println!("{}. {} ({} MB)", 
-                            i + 1,
-                            file.path.display(),
-                            file.size / 1024 / 1024
-                        );
+                }
+                Command::none()
+            }
+            Message::SaveScan => {
+                self.save_scan();
+                Command::none()
+            }
+            Message::OpenScan => {
+                self.open_scan();
+                Command::none()
+            }
+            Message::ImportScan => {
+                self.import_scan();
+                Command::none()
+            }
+            Message::SetExportFormat(format) => {
+                self.export_format = format;
+                Command::none()
+            }
+            Message::Export => {
+                self.export();
+                Command::none()
+            }
+            Message::CompareWithScan => {
+                self.compare_with_scan();
+                Command::none()
+            }
+            Message::ExitDiff => {
+                self.diff_base = None;
+                self.diff = None;
+                if self.find_node(&self.root_path).is_none() {
+                    self.root_path = self.initial_root_path.clone();
+                }
+                self.show_current();
+                Command::none()
+            }
+            Message::ToggleErrors => {
+                self.show_errors = !self.show_errors;
+                Command::none()
+            }
+            Message::ToggleLargestFiles => {
+                self.show_largest_files = !self.show_largest_files;
+                Command::none()
+            }
+            Message::ShowSmallItems(mut items) => {
+                items.sort_by_key(|entry| std::cmp::Reverse(entry.size_in(self.size_mode)));
+                self.small_items = Some(items);
+                Command::none()
+            }
+            Message::CloseSmallItems => {
+                self.small_items = None;
+                Command::none()
+            }
+            Message::CancelScan => {
+                self.scan_cancel.cancel();
+                Command::none()
+            }
+            Message::ScanProgress(progress) => {
+                self.scan_progress = Some(progress);
+                Command::none()
+            }
+            Message::ScanComplete(result) => {
+                match result {
+                    Ok(result) => {
+                        let ScanResult { info, tree, errors, dirs_scanned, dirs_reused, hard_links } = *result;
+                        self.scan_dirs = Some((dirs_scanned, dirs_reused));
+                        self.start_watching(&tree, &info.options, hard_links);
+                        self.tree = Some(tree);
+                        self.scan_info = Some(info);
+                        self.scan_errors = errors;
+                    }
+                    Err(error) => {
+                        self.tree = None;
+                        self.scan_info = None;
+                        self.scan_dirs = None;
+                        self.scan_errors = vec![error];
                     }
-                    
-                    self.treemap = TreeMap::new(self.root_path.clone());
-                    self.treemap.entries = entries;
-                    self.treemap.update_layout(Rectangle {
-                        x: 0.0,
-                        y: 0.0,
-                        width: 1000.0,
-                        height: 800.0,
-                    });
-                    self.total_size = progress.total_size;
-                    self.scanning = false;
                 }
+                self.snapshot_path = None;
+                self.update_diff();
+                if self.scan_errors.is_empty() {
+                    self.show_errors = false;
+                }
+                if !self.can_show(&self.root_path) {
+                    self.root_path = self.initial_root_path.clone();
+                }
+                self.show_current();
+                self.scanning = false;
+                self.scan_previous = None;
+                self.scan_progress = None;
+                Command::none()
+            }
+            Message::FsChanged(changes) => {
+                let changed = self.tree.as_mut().is_some_and(|tree| apply_changes(tree, changes));
+                if changed {
+                    self.update_diff();
+                    if !self.can_show(&self.root_path) {
+                        self.root_path = self.initial_root_path.clone();
+                    }
+                    self.show_current();
+                }
+                Command::none()
+            }
+            Message::WatchFailed(error) => {
+                self.stop_watching();
+                self.watch_error = Some(error);
                 Command::none()
             }
-            Message::ScanProgress(_) => Command::none(),
-            Message::ScanComplete(_) => Command::none(),
             Message::Select(path) => {
                 println!("Select message received with path: {:?}", path);
                 *SELECTED_PATH.lock().unwrap() = path;
                 Command::none()
             }
+            Message::ToggleSizeMode => {
+                self.size_mode = self.size_mode.toggled();
+                self.update_diff();
+                self.show_current();
+                Command::none()
+            }
+            Message::SetTreemapDepth(depth) => {
+                self.treemap_depth = depth.clamp(1, MAX_TREEMAP_DEPTH);
+                self.show_current();
+                Command::none()
+            }
+            Message::SetColorScale(scale) => {
+                self.color_scale = scale;
+                self.treemap.color_scale = scale;
+                Command::none()
+            }
+            Message::ToggleSettings => {
+                self.show_settings = !self.show_settings;
+                Command::none()
+            }
+            Message::SetOneFileSystem(one_file_system) => {
+                self.scan_options.one_file_system = one_file_system;
+                Command::none()
+            }
+            Message::SetSymlinkPolicy(policy) => {
+                self.scan_options.symlinks = policy;
+                Command::none()
+            }
+            Message::SetThreads(threads) => {
+                self.scan_options.threads = threads.max(1);
+                Command::none()
+            }
+            Message::AgeBreakpointsChanged(input) => {
+                if let Some(breakpoints) = parse_age_breakpoints(&input) {
+                    self.treemap.age_breakpoints = breakpoints.clone();
+                    self.age_breakpoints = breakpoints;
+                }
+                self.age_input = input;
+                Command::none()
+            }
+            Message::ExcludeInputChanged(input) => {
+                self.exclude_input = input;
+                Command::none()
+            }
+            Message::AddExclude => {
+                self.scan_options.exclude.add(&self.exclude_input);
+                self.exclude_input.clear();
+                Command::none()
+            }
+            Message::RemoveExcludePattern(index) => {
+                if index < self.scan_options.exclude.patterns.len() {
+                    self.scan_options.exclude.patterns.remove(index);
+                }
+                Command::none()
+            }
+            Message::RemoveExcludePath(index) => {
+                if index < self.scan_options.exclude.paths.len() {
+                    self.scan_options.exclude.paths.remove(index);
+                }
+                Command::none()
+            }
+            Message::SetUseIgnoreFiles(use_ignore_files) => {
+                self.scan_options.exclude.use_ignore_files = use_ignore_files;
+                Command::none()
+            }
+            Message::SetMeasureExcluded(measure) => {
+                self.scan_options.exclude.measure = measure;
+                Command::none()
+            }
+            Message::IncludeMount(path) => {
+                if !self.scan_options.include_mounts.contains(&path) {
+                    self.scan_options.include_mounts.push(path);
+                }
+                Command::perform(async {}, |_| Message::Scan)
+            }
             Message::DrillDown => {
                 let path_to_drill = SELECTED_PATH.lock()
                     .unwrap()
                     .clone()
-                    .filter(|p| p.is_dir());
+                    .filter(|p| self.can_drill_into(p));
 
                 if let Some(path) = path_to_drill {
                     println!("Drilling down to: {:?}", path);
-                    self.root_path = path.clone();
-                    self.treemap = TreeMap::new(self.root_path.clone());
-                    return Command::perform(async {}, |_| Message::Scan);
+                    self.root_path = path;
+                    self.show_current();
                 }
                 Command::none()
             }
@@ -171,8 +457,7 @@ impl Application for SpaceExplorer {
                     if self.root_path != self.initial_root_path {
                         println!("Drilling up to: {:?}", parent);
                         self.root_path = parent.to_path_buf();
-                        self.treemap = TreeMap::new(self.root_path.clone());
-                        return Command::perform(async {}, |_| Message::Scan);
+                        self.show_current();
                     }
                 }
                 Command::none()
@@ -214,7 +499,11 @@ impl Application for SpaceExplorer {
                                 .unwrap_or(());
                         } else {
                             *SELECTED_PATH.lock().unwrap() = None;
-                            return Command::perform(async {}, |_| Message::Scan);
+                            if let Some(tree) = &mut self.tree {
+                                tree.remove(&path);
+                            }
+                            self.update_diff();
+                            self.show_current();
                         }
                     }
                 }
@@ -226,22 +515,104 @@ impl Application for SpaceExplorer {
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let title = text("Mac Space Explorer")
             .size(40)
             .style(Color::from_rgb(0.4, 0.4, 1.0));
 
-        let path_text = text(format!("Path: {}", self.root_path.display()))
-            .size(16)
-            .style(Color::from_rgb(0.7, 0.7, 0.7));
+        let path_text = text(match (&self.snapshot_path, &self.scan_info) {
+            (Some(file), Some(info)) => format!(
+                "Path: {} (snapshot {}, scanned on {} at {})",
+                self.root_path.display(),
+                file.display(),
+                info.host,
+                chrono::DateTime::<chrono::Local>::from(info.scanned_at).format("%Y-%m-%d %H:%M"),
+            ),
+            _ => format!("Path: {}", self.root_path.display()),
+        })
+        .size(16)
+        .style(Color::from_rgb(0.7, 0.7, 0.7));
 
+        let is_partial = self.find_node(&self.root_path).is_some_and(|node| node.partial);
         let total_size_text = text(format!(
-            "Total Size: {} MB",
-            (self.total_size / 1024 / 1024).separate_with_commas()
+            "Total Size ({}): {} MB{}",
+            self.size_mode.label(),
+            (self.total_size / 1024 / 1024).separate_with_commas(),
+            if is_partial { " (partial: scan was stopped)" } else { "" }
         ))
         .size(16)
         .style(Color::from_rgb(0.7, 0.7, 0.7));
 
+        let excluded = self.find_node(&self.root_path).map_or(0, |node| node.excluded);
+        let excluded_text = text(if self.excluded_unmeasured {
+            format!(
+                "Excluded: {} MB plus folders not measured",
+                (excluded / 1024 / 1024).separate_with_commas()
+            )
+        } else if excluded > 0 {
+            format!("Excluded: {} MB", (excluded / 1024 / 1024).separate_with_commas())
+        } else {
+            String::new()
+        })
+        .size(16)
+        .style(Color::from_rgb(0.7, 0.7, 0.7));
+
+        let diff_text = text(match (self.diff.as_ref().and_then(|d| d.find(&self.root_path)), &self.diff_base) {
+            (Some(diff), Some(base)) => format!(
+                "Since {}: {} ({} MB added, {} MB removed; {} → {} files: {} added, {} removed, {} changed)",
+                chrono::DateTime::<chrono::Local>::from(base.info.scanned_at).format("%Y-%m-%d %H:%M"),
+                delta_text(diff.delta()),
+                (diff.added / 1024 / 1024).separate_with_commas(),
+                (diff.removed / 1024 / 1024).separate_with_commas(),
+                diff.old_files.separate_with_commas(),
+                diff.new_files.separate_with_commas(),
+                diff.added_files.separate_with_commas(),
+                diff.removed_files.separate_with_commas(),
+                diff.changed_files.separate_with_commas(),
+            ),
+            _ => String::new(),
+        })
+        .size(16)
+        .style(Color::from_rgb(0.9, 0.6, 0.3));
+
+        let reuse_text = text(match self.scan_dirs {
+            Some((scanned, reused)) if reused > 0 => format!(
+                "Listed {} directories, reused {} unchanged listings",
+                scanned.separate_with_commas(),
+                reused.separate_with_commas()
+            ),
+            _ => String::new(),
+        })
+        .size(16)
+        .style(Color::from_rgb(0.7, 0.7, 0.7));
+
+        let watch_text = match (&self.watcher, &self.watch_error) {
+            (Some(_), _) => text("● Live").style(Color::from_rgb(0.3, 0.8, 0.4)),
+            (None, Some(error)) => text(format!("Live updates off: {}", error))
+                .style(Color::from_rgb(0.9, 0.5, 0.3)),
+            (None, None) => text(""),
+        }
+        .size(16);
+
+        let status_row = if self.scan_errors.is_empty() {
+            row![total_size_text, excluded_text, reuse_text, watch_text].spacing(10)
+        } else {
+            row![
+                total_size_text,
+                excluded_text,
+                reuse_text,
+                watch_text,
+                button(text(format!(
+                    "⚠ {} items could not be read",
+                    self.scan_errors.len().separate_with_commas()
+                )).size(14))
+                .style(theme::Button::Secondary)
+                .on_press(Message::ToggleErrors),
+            ]
+            .spacing(10)
+            .align_items(iced::Alignment::Center)
+        };
+
         let button_row = {
             // Get selected path once to avoid multiple locks
             let selected = SELECTED_PATH.lock().unwrap().clone();
@@ -250,7 +621,30 @@ impl Application for SpaceExplorer {
                 button("Select Folder").on_press(Message::SelectFolder),
                 button("Scan").on_press(Message::Scan),
                 button("Drill Up").on_press(Message::DrillUp),
-                if selected.as_ref().map_or(false, |p| p.is_dir()) {
+                button(text(format!("Size: {}", self.size_mode.label())))
+                    .on_press(Message::ToggleSizeMode),
+                button("Settings").on_press(Message::ToggleSettings),
+                if self.tree.is_some() {
+                    button("Save Scan").on_press(Message::SaveScan)
+                } else {
+                    button("Save Scan").style(theme::Button::Secondary)
+                },
+                button("Open Scan").on_press(Message::OpenScan),
+                button("Import...").on_press(Message::ImportScan),
+                pick_list(&ExportFormat::ALL[..], Some(self.export_format), Message::SetExportFormat),
+                if self.tree.is_some() {
+                    button("Export").on_press(Message::Export)
+                } else {
+                    button("Export").style(theme::Button::Secondary)
+                },
+                if self.diff.is_some() {
+                    button("Exit Diff").on_press(Message::ExitDiff)
+                } else if self.tree.is_some() {
+                    button("Compare...").on_press(Message::CompareWithScan)
+                } else {
+                    button("Compare...").style(theme::Button::Secondary)
+                },
+                if selected.as_ref().is_some_and(|p| self.can_drill_into(p)) {
                     button("Drill Down").on_press(Message::DrillDown)
                 } else {
                     button("Drill Down").style(theme::Button::Secondary)
@@ -278,21 +672,135 @@ impl Application for SpaceExplorer {
         };
 
         let content: Element<Message> = if self.scanning {
+            let progress = self.scan_progress.clone().unwrap_or_default();
+            let current_path = progress.current_path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default();
+
             column![
                 title,
                 path_text,
                 button_row,
-                text("Scanning...").size(20),
+                row![
+                    text("Scanning...").size(20),
+                    button("Stop")
+                        .style(theme::Button::Destructive)
+                        .on_press(Message::CancelScan),
+                ]
+                .spacing(10)
+                .align_items(iced::Alignment::Center),
+                progress_bar(0.0..=1.0, progress.fraction()),
+                text(format!(
+                    "{} files, {} MB scanned ({} MB/s)",
+                    progress.files_scanned.separate_with_commas(),
+                    (progress.total_size / 1024 / 1024).separate_with_commas(),
+                    (progress.throughput() / 1024.0 / 1024.0).round() as u64,
+                ))
+                .size(16)
+                .style(Color::from_rgb(0.7, 0.7, 0.7)),
+                text(current_path)
+                    .size(14)
+                    .style(Color::from_rgb(0.7, 0.7, 0.7)),
             ]
             .spacing(20)
             .padding(20)
             .into()
         } else {
-            let legend = row![
-                text("📁 Folders").style(Color::from_rgb(0.2, 0.6, 0.6)),
-                text("📄 Files").style(Color::from_rgb(0.7, 0.2, 0.2))
+            let legend = if self.diff.is_some() {
+                row![
+                    text("▲ Grew").style(Color::from_rgb(0.8, 0.3, 0.2)),
+                    text("▼ Shrank").style(Color::from_rgb(0.2, 0.6, 0.3)),
+                    text("Area shows how much each item changed")
+                        .style(Color::from_rgb(0.7, 0.7, 0.7)),
+                ]
+                .spacing(10)
+            } else {
+                match self.color_scale {
+                    ColorScale::Category => self.category_totals.iter().fold(
+                        row![
+                            text("📁 Folders").size(14).style(Color::from_rgb(0.4, 0.42, 0.45)),
+                            text("💽 Mount Points").size(14).style(MOUNT_COLOR),
+                            text("🔗 Symlinks").size(14).style(SYMLINK_COLOR),
+                        ],
+                        |row, &(category, size)| {
+                            row.push(
+                                text(format!(
+                                    "■ {} {} MB",
+                                    category,
+                                    (size / 1024 / 1024).separate_with_commas()
+                                ))
+                                .size(14)
+                                .style(category_color(category)),
+                            )
+                        },
+                    ),
+                    ColorScale::Kind => row![
+                        text("📁 Folders").style(Color::from_rgb(0.2, 0.6, 0.6)),
+                        text("📄 Files").style(Color::from_rgb(0.7, 0.2, 0.2)),
+                        text("💽 Mount Points").style(MOUNT_COLOR),
+                        text("🔗 Symlinks").style(SYMLINK_COLOR)
+                    ],
+                    ColorScale::Size => row![
+                        text("■ Small").style(size_color(0.0)),
+                        text("■ Medium").style(size_color(0.5)),
+                        text("■ Large").style(size_color(1.0)),
+                    ],
+                    ColorScale::Depth => (0..self.treemap_depth).fold(row![], |row, depth| {
+                        row.push(text(format!("■ Level {}", depth + 1)).style(depth_color(depth)))
+                    }),
+                    ColorScale::Age => {
+                        let breakpoints = &self.age_breakpoints;
+                        let bands = breakpoints.len() + 1;
+                        (0..bands).fold(row![text("Modified:")], |row, band| {
+                            let label = match band {
+                                0 => format!("< {} days", breakpoints[0]),
+                                _ if band == breakpoints.len() => format!("≥ {} days", breakpoints[band - 1]),
+                                _ => format!("{}–{} days", breakpoints[band - 1], breakpoints[band]),
+                            };
+                            row.push(text(format!("■ {}", label)).style(age_color(band, bands)))
+                        })
+                    }
+                }
+                .spacing(10)
+            };
+
+            let levels = row![
+                text(format!("Levels: {}", self.treemap_depth)),
+                button("-").on_press(Message::SetTreemapDepth(self.treemap_depth.saturating_sub(1))),
+                button("+").on_press(Message::SetTreemapDepth(self.treemap_depth + 1)),
+                pick_list(&ColorScale::ALL[..], Some(self.color_scale), Message::SetColorScale),
             ]
-            .spacing(10);
+            .spacing(10)
+            .align_items(iced::Alignment::Center);
+            let panel_toggle = button(if self.show_largest_files {
+                if self.diff.is_some() { "Hide Growers" } else { "Hide Largest Files" }
+            } else if self.diff.is_some() {
+                "Show Growers"
+            } else {
+                "Show Largest Files"
+            })
+            .style(theme::Button::Secondary)
+            .on_press(Message::ToggleLargestFiles);
+            let controls = row![levels, panel_toggle].spacing(30).align_items(iced::Alignment::Center);
+
+            let skipped_mounts = self.find_node(&self.root_path)
+                .map(TreeNode::skipped_mounts)
+                .unwrap_or_default();
+            let mounts_row = if skipped_mounts.is_empty() {
+                row![]
+            } else {
+                skipped_mounts.into_iter()
+                    .fold(row![text("Other filesystems not scanned:").size(14)], |row, path| {
+                        row.push(
+                            button(text(format!("Scan {}", path.display())).size(14))
+                                .style(theme::Button::Secondary)
+                                .on_press(Message::IncludeMount(path)),
+                        )
+                    })
+                    .spacing(10)
+                    .align_items(iced::Alignment::Center)
+            };
 
             let treemap = canvas::Canvas::new(&self.treemap)
                 .width(Length::Fill)
@@ -301,7 +809,9 @@ impl Application for SpaceExplorer {
             // Create the largest files panel
             let largest_files_panel = {
                 let mut files_list = self.largest_files.clone();
-                files_list.sort_by(|a, b| b.size.cmp(&a.size));
+                if self.diff.is_none() {
+                    files_list.sort_by_key(|e| std::cmp::Reverse(e.size_in(self.size_mode)));
+                }
                 
                 if !files_list.is_empty() {
                     let selected = SELECTED_PATH.lock().unwrap().clone();
@@ -310,7 +820,7 @@ impl Application for SpaceExplorer {
                             .iter()
                             .enumerate()
                             .map(|(i, entry)| {
-                                let is_selected = selected.as_ref().map_or(false, |p| p == &entry.path);
+                                let is_selected = selected.as_ref() == Some(&entry.path);
                                 let name = entry.path.file_name()
                                     .unwrap_or_default()
                                     .to_string_lossy()
@@ -323,10 +833,14 @@ impl Application for SpaceExplorer {
                                     text(&name)
                                         .size(14)
                                         .width(Length::Fill),
-                                    text(format!(
-                                        "{} MB",
-                                        (entry.size / 1024 / 1024).separate_with_commas()
-                                    ))
+                                    text(if self.diff.is_some() {
+                                        delta_text(entry.delta)
+                                    } else {
+                                        format!(
+                                            "{} MB",
+                                            (entry.size_in(self.size_mode) / 1024 / 1024).separate_with_commas()
+                                        )
+                                    })
                                     .size(14)
                                     .width(Length::Fixed(100.0)),
                                 ]
@@ -358,7 +872,7 @@ impl Application for SpaceExplorer {
 
                     container(
                         column![
-                            text("Largest Files").size(20),
+                            text(if self.diff.is_some() { "Biggest Growers" } else { "Largest Files" }).size(20),
                             items,
                         ]
                         .spacing(10)
@@ -369,7 +883,7 @@ impl Application for SpaceExplorer {
                     .style(theme::Container::Box)
                 } else {
                     container(
-                        text("No files found")
+                        text(if self.diff.is_some() { "Nothing grew" } else { "No files found" })
                             .size(16)
                             .style(Color::from_rgb(0.7, 0.7, 0.7))
                     )
@@ -379,22 +893,100 @@ impl Application for SpaceExplorer {
                 }
             };
 
-            row![
+            let side_panel = if self.show_errors {
+                let items = self.scan_errors.iter().map(|error| {
+                    column![
+                        text(error.path.display().to_string()).size(14),
+                        text(error.kind.to_string())
+                            .size(12)
+                            .style(Color::from_rgb(0.9, 0.5, 0.3)),
+                    ]
+                    .into()
+                });
+
+                Some(container(
+                    column![
+                        row![
+                            text("Unreadable Items").size(20).width(Length::Fill),
+                            button("Close").on_press(Message::ToggleErrors),
+                        ]
+                        .align_items(iced::Alignment::Center),
+                        text("Totals don't include anything below these paths.")
+                            .size(14)
+                            .style(Color::from_rgb(0.7, 0.7, 0.7)),
+                        scrollable(column(items.collect()).spacing(6).width(Length::Fill)),
+                    ]
+                    .spacing(10)
+                    .width(Length::Fill)
+                )
+                .width(Length::Fixed(400.0))
+                .height(Length::Fill)
+                .padding(10)
+                .style(theme::Container::Box))
+            } else if let Some(small_items) = &self.small_items {
+                let items = small_items.iter().map(|entry| {
+                    let name = entry.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                    button(
+                        row![
+                            text(name).size(14).width(Length::Fill),
+                            text(if self.diff.is_some() {
+                                delta_text(entry.delta)
+                            } else {
+                                format!("{} KB", (entry.size_in(self.size_mode) / 1024).separate_with_commas())
+                            })
+                            .size(14),
+                        ]
+                        .spacing(5),
+                    )
+                    .on_press(Message::Select(Some(entry.path.clone())))
+                    .style(theme::Button::Text)
+                    .into()
+                });
+
+                Some(container(
+                    column![
+                        row![
+                            text(format!("{} Smaller Items", small_items.len().separate_with_commas()))
+                                .size(20)
+                                .width(Length::Fill),
+                            button("Close").on_press(Message::CloseSmallItems),
+                        ]
+                        .align_items(iced::Alignment::Center),
+                        scrollable(column(items.collect()).spacing(2).width(Length::Fill)),
+                    ]
+                    .spacing(10)
+                    .width(Length::Fill)
+                )
+                .width(Length::Fixed(400.0))
+                .height(Length::Fill)
+                .padding(10)
+                .style(theme::Container::Box))
+            } else {
+                self.show_largest_files.then_some(largest_files_panel)
+            };
+
+            let content = row![
                 column![
                     title,
                     path_text,
-                    total_size_text,
+                    status_row,
+                    diff_text,
                     button_row,
-                    legend,
-                    treemap,
+                    if self.show_settings {
+                        self.settings_view()
+                    } else {
+                        column![controls, legend, mounts_row, treemap].spacing(20).into()
+                    },
                 ]
                 .spacing(20)
                 .padding(20)
                 .width(Length::Fill),
-                largest_files_panel,
             ]
-            .width(Length::Fill)
-            .into()
+            .width(Length::Fill);
+            match side_panel {
+                Some(panel) => content.push(panel).into(),
+                None => content.into(),
+            }
         };
 
         container(content)
@@ -405,6 +997,361 @@ impl Application for SpaceExplorer {
 }
 
 impl SpaceExplorer {
+    fn find_node(&self, path: &std::path::Path) -> Option<&TreeNode> {
+        self.tree.as_ref().and_then(|tree| tree.find(path))
+    }
+
+    /// Refreshes the treemap, totals and largest-files panel from the
+    /// cached tree node for `root_path`.
+    fn show_current(&mut self) {
+        self.small_items = None;
+        if let Some(diff) = self.diff.as_ref().and_then(|diff| diff.find(&self.root_path)) {
+            let node = self.tree.as_ref().and_then(|tree| tree.find(&self.root_path));
+            let entries = diff.child_entries(node);
+            self.largest_files = diff.biggest_growers(10, node);
+            self.category_totals.clear();
+            self.excluded_unmeasured = node.is_some_and(TreeNode::excludes_unmeasured);
+            self.total_size = diff.new_size;
+            self.treemap = TreeMap::new();
+            self.treemap.size_mode = self.size_mode;
+            self.treemap.diff = true;
+            self.treemap.depth = self.treemap_depth;
+            self.treemap.color_scale = self.color_scale;
+            self.treemap.age_breakpoints = self.age_breakpoints.clone();
+            self.treemap.nested = diff.nested_entries(self.treemap_depth - 1, node);
+            self.treemap.entries = entries;
+            return;
+        }
+        let Some(node) = self.tree.as_ref().and_then(|tree| tree.find(&self.root_path)) else {
+            return;
+        };
+
+        let entries = node.child_entries();
+        let total_size = node.size_in(self.size_mode);
+        self.largest_files = node.largest_files(10, self.size_mode);
+        println!("Found {} largest files in {}", self.largest_files.len(), self.root_path.display());
+
+        for (i, file) in self.largest_files.iter().enumerate() {
+            println!("{}. {} ({} MB)",
+                i + 1,
+                file.path.display(),
+                file.size_in(self.size_mode) / 1024 / 1024
+            );
+        }
+
+        self.treemap = TreeMap::new();
+        self.treemap.size_mode = self.size_mode;
+        self.treemap.depth = self.treemap_depth;
+        self.treemap.color_scale = self.color_scale;
+        self.treemap.age_breakpoints = self.age_breakpoints.clone();
+        self.treemap.nested = node.nested_entries(self.treemap_depth - 1);
+        self.treemap.entries = entries;
+        self.category_totals = category::breakdown(node, self.size_mode);
+        self.excluded_unmeasured = node.excludes_unmeasured();
+        self.total_size = total_size;
+    }
+
+    /// Whether `path` can be shown: it exists in the current tree or, in diff
+    /// mode, in either of the compared trees.
+    fn can_show(&self, path: &std::path::Path) -> bool {
+        match &self.diff {
+            Some(diff) => diff.find(path).is_some(),
+            None => self.find_node(path).is_some(),
+        }
+    }
+
+    fn can_drill_into(&self, path: &std::path::Path) -> bool {
+        match &self.diff {
+            Some(diff) => diff.find(path).is_some_and(|node| node.is_dir),
+            None => self.find_node(path).is_some_and(|node| node.is_dir),
+        }
+    }
+
+    /// Recomputes the diff against `diff_base`, dropping it if the current
+    /// tree is of a different root.
+    fn update_diff(&mut self) {
+        self.diff = match (&self.diff_base, &self.tree) {
+            (Some(base), Some(tree)) if base.info.root == tree.path => {
+                Some(diff_trees(&base.tree, tree, self.size_mode))
+            }
+            _ => None,
+        };
+        if self.diff.is_none() {
+            self.diff_base = None;
+        }
+    }
+
+    /// Starts patching `tree` as its directories change on disk. The
+    /// watches are added by `watch_subscription`, off the UI thread.
+    fn start_watching(&mut self, tree: &TreeNode, options: &ScanOptions, hard_links: HashMap<(u64, u64), PathBuf>) {
+        self.stop_watching();
+        self.watcher = Some(Arc::new(Mutex::new(Watcher::new(tree, options.clone(), hard_links))));
+    }
+
+    fn stop_watching(&mut self) {
+        self.watch_cancel.cancel();
+        self.watch_cancel = CancelToken::new();
+        self.watcher = None;
+        self.watch_error = None;
+    }
+
+    fn settings_view(&self) -> Element<'_, Message> {
+        let options = &self.scan_options;
+        let muted = Color::from_rgb(0.7, 0.7, 0.7);
+
+        let symlinks = SymlinkPolicy::ALL.iter().fold(
+            row![text("Symlinks:").size(16)].spacing(15),
+            |row, policy| {
+                row.push(radio(
+                    policy.label(),
+                    *policy,
+                    Some(options.symlinks),
+                    Message::SetSymlinkPolicy,
+                ))
+            },
+        );
+
+        let threads = row![
+            text(format!("Scan threads: {}", options.threads)).size(16),
+            button("-").on_press(Message::SetThreads(options.threads.saturating_sub(1))),
+            button("+").on_press(Message::SetThreads(options.threads + 1)),
+        ]
+        .spacing(10)
+        .align_items(iced::Alignment::Center);
+
+        let rule_row = |label: String, on_remove: Message| {
+            row![
+                text(label).size(14).width(Length::Fill),
+                button(text("Remove").size(14))
+                    .style(theme::Button::Secondary)
+                    .on_press(on_remove),
+            ]
+            .spacing(10)
+            .align_items(iced::Alignment::Center)
+            .into()
+        };
+        let rules: Vec<Element<_>> = options.exclude.paths.iter()
+            .enumerate()
+            .map(|(i, path)| rule_row(format!("Path: {}", path.display()), Message::RemoveExcludePath(i)))
+            .chain(options.exclude.patterns.iter()
+                .enumerate()
+                .map(|(i, pattern)| rule_row(format!("Pattern: {}", pattern), Message::RemoveExcludePattern(i))))
+            .collect();
+        let rules: Element<_> = if rules.is_empty() {
+            text("Nothing is excluded.").size(14).style(muted).into()
+        } else {
+            scrollable(column(rules).spacing(4)).height(Length::Fixed(200.0)).into()
+        };
+
+        container(
+            column![
+                text("Scan Settings").size(24),
+                checkbox(
+                    "Stay on one filesystem (don't descend into other mounts)",
+                    options.one_file_system,
+                    Message::SetOneFileSystem,
+                ),
+                symlinks,
+                threads,
+                text("Exclude").size(20),
+                text("Absolute paths, or glob patterns such as node_modules, *.tmp or /Users/*/Library/Caches. \
+                      Patterns without a / match names at any depth.")
+                    .size(14)
+                    .style(muted),
+                row![
+                    text_input("Path or pattern to exclude", &self.exclude_input)
+                        .on_input(Message::ExcludeInputChanged)
+                        .on_submit(Message::AddExclude),
+                    button("Add").on_press(Message::AddExclude),
+                ]
+                .spacing(10),
+                rules,
+                checkbox(
+                    "Honor .gitignore and .ignore files",
+                    options.exclude.use_ignore_files,
+                    Message::SetUseIgnoreFiles,
+                ),
+                checkbox(
+                    "Measure excluded folders (reads them in full)",
+                    options.exclude.measure,
+                    Message::SetMeasureExcluded,
+                ),
+                text("Age Colors").size(20),
+                text("Ages in days, ascending, at which the age color scale turns staler.")
+                    .size(14)
+                    .style(muted),
+                text_input("7, 30, 180, 365", &self.age_input)
+                    .on_input(Message::AgeBreakpointsChanged),
+                if parse_age_breakpoints(&self.age_input).is_some() {
+                    text("")
+                } else {
+                    text("Enter whole numbers of days separated by commas, smallest first.")
+                        .size(14)
+                        .style(Color::from_rgb(0.9, 0.5, 0.3))
+                },
+                row![
+                    button("Apply & Rescan").on_press(Message::Scan),
+                    button("Close")
+                        .style(theme::Button::Secondary)
+                        .on_press(Message::ToggleSettings),
+                ]
+                .spacing(10),
+            ]
+            .spacing(15)
+        )
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .padding(10)
+        .style(theme::Container::Box)
+        .into()
+    }
+
+    fn save_scan(&self) {
+        let (Some(tree), Some(info)) = (&self.tree, &self.scan_info) else {
+            return;
+        };
+        let default_name = format!(
+            "{}.{}",
+            info.root.file_name().unwrap_or_default().to_string_lossy(),
+            SNAPSHOT_EXTENSION
+        );
+        let Ok(Some(path)) = FileDialog::new()
+            .set_filename(&default_name)
+            .add_filter("Scan snapshot", &[SNAPSHOT_EXTENSION])
+            .show_save_single_file()
+        else {
+            return;
+        };
+
+        let snapshot = Snapshot {
+            info: info.clone(),
+            tree: tree.clone(),
+            errors: self.scan_errors.clone(),
+        };
+        if let Err(e) = snapshot::save(&path, &snapshot) {
+            MessageDialog::new()
+                .set_title("Error")
+                .set_text(&format!("Failed to save scan: {}", e))
+                .set_type(MessageType::Error)
+                .show_alert()
+                .unwrap_or(());
+        }
+    }
+
+    fn export(&self) {
+        let (Some(tree), Some(info)) = (&self.tree, &self.scan_info) else {
+            return;
+        };
+        let format = self.export_format;
+        let default_name = format!(
+            "{}.{}",
+            info.root.file_name().unwrap_or_default().to_string_lossy(),
+            format.extension()
+        );
+        let Ok(Some(path)) = FileDialog::new()
+            .set_filename(&default_name)
+            .add_filter(&format!("{} export", format), &[format.extension()])
+            .show_save_single_file()
+        else {
+            return;
+        };
+
+        if let Err(e) = export::export_file(&path, format, info, tree, &self.scan_errors) {
+            MessageDialog::new()
+                .set_title("Error")
+                .set_text(&format!("Failed to export scan: {}", e))
+                .set_type(MessageType::Error)
+                .show_alert()
+                .unwrap_or(());
+        }
+    }
+
+    fn open_scan(&mut self) {
+        let Ok(Some(path)) = FileDialog::new()
+            .add_filter("Scan snapshot", &[SNAPSHOT_EXTENSION])
+            .show_open_single_file()
+        else {
+            return;
+        };
+        let snapshot = snapshot::load(&path);
+        self.show_snapshot(path, snapshot, "open scan");
+    }
+
+    /// Loads an `ncdu -o` dump or `du -ab` listing taken elsewhere.
+    fn import_scan(&mut self) {
+        let Ok(Some(path)) = FileDialog::new().show_open_single_file() else {
+            return;
+        };
+        let snapshot = import::import_file(&path);
+        self.show_snapshot(path, snapshot, "import");
+    }
+
+    fn show_snapshot(&mut self, path: PathBuf, snapshot: std::io::Result<Snapshot>, action: &str) {
+        match snapshot {
+            Ok(Snapshot { info, tree, errors }) => {
+                self.scan_cancel.cancel();
+                self.scanning = false;
+                self.scan_previous = None;
+                // A snapshot shows the disk as it was, so it isn't patched.
+                self.stop_watching();
+                *SELECTED_PATH.lock().unwrap() = None;
+                self.root_path = info.root.clone();
+                self.initial_root_path = info.root.clone();
+                self.tree = Some(tree);
+                self.scan_info = Some(info);
+                self.scan_errors = errors;
+                self.scan_dirs = None;
+                self.snapshot_path = Some(path);
+                self.update_diff();
+                self.show_current();
+            }
+            Err(e) => {
+                MessageDialog::new()
+                    .set_title("Error")
+                    .set_text(&format!("Failed to {} {}: {}", action, path.display(), e))
+                    .set_type(MessageType::Error)
+                    .show_alert()
+                    .unwrap_or(());
+            }
+        }
+    }
+
+    /// Loads an older snapshot of the current root and shows what changed
+    /// since it was taken.
+    fn compare_with_scan(&mut self) {
+        let Some(tree) = &self.tree else {
+            return;
+        };
+        let Ok(Some(path)) = FileDialog::new()
+            .add_filter("Scan snapshot", &[SNAPSHOT_EXTENSION])
+            .show_open_single_file()
+        else {
+            return;
+        };
+
+        let error = match snapshot::load(&path) {
+            Ok(base) if base.info.root == tree.path => {
+                self.diff_base = Some(base);
+                self.update_diff();
+                self.show_current();
+                return;
+            }
+            Ok(base) => format!(
+                "{} is a scan of {}, not {}",
+                path.display(),
+                base.info.root.display(),
+                tree.path.display()
+            ),
+            Err(e) => format!("Failed to open scan: {}", e),
+        };
+        MessageDialog::new()
+            .set_title("Error")
+            .set_text(&error)
+            .set_type(MessageType::Error)
+            .show_alert()
+            .unwrap_or(());
+    }
+
     fn open_in_explorer(&self) {
         if let Some(path) = SELECTED_PATH.lock().unwrap().as_ref() {
             let parent = if path.is_file() {
@@ -425,6 +1372,78 @@ impl SpaceExplorer {
     }
 }
 
+/// Scans `root` on a blocking thread, streaming `ScanProgress` snapshots
+/// while it runs and a final `ScanComplete` with the tree, which is partial
+/// if `cancel` fired first.
+fn scan_subscription(
+    id: u64,
+    root: PathBuf,
+    options: ScanOptions,
+    previous: Option<Arc<TreeNode>>,
+    cancel: CancelToken,
+) -> Subscription<Message> {
+    struct Scan;
+
+    subscription::channel((std::any::TypeId::of::<Scan>(), id), 100, move |mut output| async move {
+        let progress = Arc::new(Mutex::new(ScanProgress::default()));
+        let root_path = root.clone();
+        let scan = tokio::task::spawn_blocking({
+            let progress = progress.clone();
+            move || match previous {
+                Some(previous) => rescan_tree(&root, &options, Some(&previous), &progress, &cancel),
+                None => scan_tree(&root, &options, &progress, &cancel),
+            }
+        });
+
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        while !scan.is_finished() {
+            interval.tick().await;
+            let snapshot = progress.lock().unwrap().clone();
+            let _ = output.send(Message::ScanProgress(snapshot)).await;
+        }
+
+        let result = match scan.await {
+            Ok(result) => result.map(Box::new),
+            Err(error) => Err(ScanError {
+                path: root_path,
+                kind: ScanErrorKind::Io(error.to_string()),
+            }),
+        };
+        let _ = output.send(Message::ScanComplete(result)).await;
+
+        std::future::pending().await
+    })
+}
+
+fn watch_subscription(id: u64, watcher: Arc<Mutex<Watcher>>, cancel: CancelToken) -> Subscription<Message> {
+    struct Watch;
+
+    subscription::channel((std::any::TypeId::of::<Watch>(), id), 100, move |mut output| async move {
+        while !cancel.is_cancelled() {
+            let batch = tokio::task::spawn_blocking({
+                let watcher = watcher.clone();
+                let cancel = cancel.clone();
+                move || watcher.lock().unwrap().next_changes(&cancel)
+            })
+            .await;
+
+            let message = match batch {
+                Ok(Ok(changes)) if changes.is_empty() => continue,
+                Ok(Ok(changes)) => Message::FsChanged(changes),
+                Ok(Err(error)) => Message::WatchFailed(error.to_string()),
+                Err(error) => Message::WatchFailed(error.to_string()),
+            };
+            let failed = matches!(message, Message::WatchFailed(_));
+            let _ = output.send(message).await;
+            if failed {
+                break;
+            }
+        }
+
+        std::future::pending().await
+    })
+}
+
 struct SelectedStyle;
 
 impl container::StyleSheet for SelectedStyle {
@@ -440,5 +1459,38 @@ impl container::StyleSheet for SelectedStyle {
 }
 
 pub fn main() -> iced::Result {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    if cli::is_cli(&args) {
+        std::process::exit(cli::run(&args));
+    }
     SpaceExplorer::run(Settings::default())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::scanner::tests::{fixture, scan};
+    use iced::Size;
+    use std::fs;
+
+    #[test]
+    fn diff_mode_draws_nested_levels() {
+        let root = fixture("diff-nested", 2, 2, 1);
+        let old = scan(&root, 2);
+        fs::write(root.join("dir0").join("dir1").join("grown.bin"), vec![0u8; 5000]).unwrap();
+        let new = scan(&root, 2);
+
+        let (mut app, _) = SpaceExplorer::new(());
+        app.root_path = root.clone();
+        app.diff = Some(diff_trees(&old, &new, app.size_mode));
+        app.tree = Some(new);
+        app.show_current();
+
+        assert!(app.treemap.diff);
+        assert_eq!(app.treemap.depth, app.treemap_depth);
+        let layout = app.treemap.layout(Size::new(800.0, 600.0));
+        assert!(layout.iter().any(|item| item.depth > 0), "no nested cells in diff mode");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
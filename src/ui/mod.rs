@@ -1,2 +1 @@
-pub mod heat_map;
 pub mod treemap;
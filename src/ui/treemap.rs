@@ -2,133 +2,534 @@ use iced::{
     widget::canvas::{self, Frame, Geometry, Path, Stroke, Event},
     Color, Point, Rectangle, Size, mouse,
 };
-use std::path::PathBuf;
+use std::{
+    borrow::Cow,
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    time::SystemTime,
+};
 use thousands::Separable;
 
-use crate::core::scanner::FileEntry;
+use crate::core::category::{self, Category};
+use crate::core::scanner::{FileEntry, SizeMode};
+
+/// Gap between a directory's border and the children nested inside it.
+const NEST_PADDING: f32 = 3.0;
+
+/// Height of the strip naming a directory above its nested children.
+const HEADER_HEIGHT: f32 = 16.0;
+
+/// Smallest inner width and height worth nesting children into.
+const MIN_NESTED_SIZE: f32 = 12.0;
+
+/// Cells smaller than this many square pixels are folded into a single
+/// cell for all of them.
+const MIN_CELL_AREA: f64 = 36.0;
+
+/// Font size of the names and sizes drawn inside rectangles.
+const LABEL_SIZE: f32 = 12.0;
+
+/// Rough width of a label character at `LABEL_SIZE`.
+const LABEL_CHAR_WIDTH: f32 = 7.0;
+
+/// Gap between a rectangle's edge and its label.
+const LABEL_PADDING: f32 = 4.0;
+
+/// Colors used for the stops of the size scale, smallest first.
+const SIZE_SCALE: [Color; 3] = [
+    Color { r: 0.15, g: 0.35, b: 0.55, a: 1.0 },
+    Color { r: 0.9, g: 0.75, b: 0.25, a: 1.0 },
+    Color { r: 0.8, g: 0.2, b: 0.15, a: 1.0 },
+];
+
+/// Colors used for the stops of the age scale, freshest first.
+const AGE_SCALE: [Color; 3] = [
+    Color { r: 0.3, g: 0.7, b: 0.45, a: 1.0 },
+    Color { r: 0.75, g: 0.7, b: 0.3, a: 1.0 },
+    Color { r: 0.8, g: 0.35, b: 0.15, a: 1.0 },
+];
+
+/// Ages in days at which cells move to the next, staler, color.
+pub const DEFAULT_AGE_BREAKPOINTS: [u64; 4] = [7, 30, 180, 365];
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Colors for successive nesting levels, repeating past the last.
+const DEPTH_COLORS: [Color; 6] = [
+    Color { r: 0.2, g: 0.6, b: 0.6, a: 1.0 },
+    Color { r: 0.35, g: 0.55, b: 0.3, a: 1.0 },
+    Color { r: 0.75, g: 0.55, b: 0.2, a: 1.0 },
+    Color { r: 0.7, g: 0.3, b: 0.4, a: 1.0 },
+    Color { r: 0.45, g: 0.35, b: 0.7, a: 1.0 },
+    Color { r: 0.3, g: 0.45, b: 0.75, a: 1.0 },
+];
+
+/// Fill for symlinks under the kind and category scales, since a link's
+/// name says nothing about what it points to.
+pub const SYMLINK_COLOR: Color = Color { r: 0.8, g: 0.6, b: 0.2, a: 1.0 };
+
+/// Fill for mount points under the kind and category scales.
+pub const MOUNT_COLOR: Color = Color { r: 0.5, g: 0.3, b: 0.7, a: 1.0 };
+
+/// What the fill color of a treemap cell shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScale {
+    /// Files by category, such as video or code.
+    #[default]
+    Category,
+    /// Files, directories, mount points and symlinks each in their own color.
+    Kind,
+    /// From cool to hot by size, on a log scale against the whole view.
+    Size,
+    /// One color per nesting level.
+    Depth,
+    /// From fresh to stale by time since the newest modification at or
+    /// below each entry.
+    Age,
+}
+
+impl ColorScale {
+    pub const ALL: [ColorScale; 5] =
+        [ColorScale::Category, ColorScale::Kind, ColorScale::Size, ColorScale::Depth, ColorScale::Age];
+}
+
+impl fmt::Display for ColorScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorScale::Category => "Color: Category",
+            ColorScale::Kind => "Color: Kind",
+            ColorScale::Size => "Color: Size",
+            ColorScale::Depth => "Color: Depth",
+            ColorScale::Age => "Color: Age",
+        })
+    }
+}
 
 pub struct TreeMap {
     pub entries: Vec<FileEntry>,
-    pub current_path: PathBuf,
-    pub rects: Vec<ItemRect>,
+    pub size_mode: SizeMode,
+    /// Lay out and color entries by how much they changed instead of by size.
+    pub diff: bool,
+    /// Number of directory levels drawn, counting `entries` as the first.
+    pub depth: usize,
+    pub color_scale: ColorScale,
+    /// Ascending ages in days splitting the age scale into bands.
+    pub age_breakpoints: Vec<u64>,
+    /// Child entries of directories below `entries`, keyed by their path,
+    /// for the levels nested inside them.
+    pub nested: HashMap<PathBuf, Vec<FileEntry>>,
+    /// Items laid out for the canvas size they were last drawn at. The
+    /// treemap is rebuilt whenever what it shows changes, so the size is
+    /// all that can make this stale.
+    layout: RefCell<Option<(Size, Vec<ItemRect>)>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ItemRect {
     pub entry: FileEntry,
     pub bounds: Rectangle,
+    /// Nesting level, 0 for `TreeMap::entries`.
+    pub depth: usize,
+    /// Set for directories with their children drawn inside them below a
+    /// header strip.
+    pub nested: bool,
+    /// Entries too small to draw on their own, for the cell standing in for
+    /// them. Its `entry` sums them up.
+    pub small: Vec<FileEntry>,
+}
+
+impl ItemRect {
+    pub fn name(&self) -> Cow<'_, str> {
+        if self.small.is_empty() {
+            self.entry.path.file_name().unwrap_or_default().to_string_lossy()
+        } else {
+            Cow::Owned(format!("{} smaller items", self.small.len().separate_with_commas()))
+        }
+    }
 }
 
 impl TreeMap {
-    pub fn new(current_path: PathBuf) -> Self {
+    pub fn new() -> Self {
         Self {
             entries: Vec::new(),
-            current_path,
-            rects: Vec::new(),
+            size_mode: SizeMode::default(),
+            diff: false,
+            depth: 1,
+            color_scale: ColorScale::default(),
+            age_breakpoints: DEFAULT_AGE_BREAKPOINTS.to_vec(),
+            nested: HashMap::new(),
+            layout: RefCell::new(None),
         }
     }
 
-    pub fn update_layout(&mut self, bounds: Rectangle) {
-        if self.entries.is_empty() {
-            return;
+    fn weight(&self, entry: &FileEntry) -> u64 {
+        if self.diff {
+            entry.delta.unsigned_abs()
+        } else {
+            entry.size_in(self.size_mode)
         }
+    }
 
-        self.rects.clear();
-        let total_size = self.entries.iter().map(|e| e.size).sum::<u64>() as f32;
-        if total_size == 0.0 {
-            return;
+    /// The items laid out on a canvas of `size`, nesting the children of
+    /// directories inside them down to `depth` levels. Parents come before
+    /// their children. Recomputed only when `size` changes.
+    pub fn layout(&self, size: Size) -> Ref<'_, [ItemRect]> {
+        let stale = self.layout.borrow().as_ref().is_none_or(|(laid_out, _)| *laid_out != size);
+        if stale {
+            let mut rects = Vec::new();
+            self.layout_level(&mut rects, &self.entries, Rectangle::with_size(size), 0);
+            *self.layout.borrow_mut() = Some((size, rects));
         }
+        Ref::map(self.layout.borrow(), |layout| layout.as_ref().map_or(&[][..], |(_, rects)| rects))
+    }
 
-        let mut remaining_area = bounds;
-        let mut remaining_entries = self.entries.clone();
-        remaining_entries.sort_by(|a, b| b.size.cmp(&a.size));
-
-        while !remaining_entries.is_empty() && remaining_area.height > 0.0 && remaining_area.width > 0.0 {
-            let remaining_size = remaining_entries.iter().map(|e| e.size).sum::<u64>() as f32;
-            let (row, rest) = self.calculate_row(&remaining_entries, remaining_area, remaining_size);
-            
-            if !row.is_empty() {
-                let row_size: u64 = row.iter().map(|e| e.size).sum();
-                let row_height = ((row_size as f32 / total_size) * bounds.height).min(remaining_area.height);
-                let mut x = remaining_area.x;
-                
-                for entry in row {
-                    let width = ((entry.size as f32 / row_size as f32) * remaining_area.width).max(0.0);
-                    if width > 0.0 {
-                        self.rects.push(ItemRect {
-                            entry,
-                            bounds: Rectangle {
-                                x,
-                                y: remaining_area.y,
-                                width,
-                                height: row_height,
-                            },
-                        });
-                        x += width;
-                    }
-                }
-                
-                remaining_area.y += row_height;
-                remaining_area.height -= row_height;
+    fn layout_level(&self, rects: &mut Vec<ItemRect>, entries: &[FileEntry], bounds: Rectangle, depth: usize) {
+        let mut entries: Vec<&FileEntry> = entries.iter().filter(|entry| self.weight(entry) > 0).collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(self.weight(entry)));
+
+        let mut weights: Vec<f64> = entries.iter().map(|entry| self.weight(entry) as f64).collect();
+        let scale = f64::from(bounds.width) * f64::from(bounds.height) / weights.iter().sum::<f64>();
+        let visible = weights.iter().take_while(|weight| *weight * scale >= MIN_CELL_AREA).count();
+        let small: Vec<FileEntry> = if entries.len() - visible > 1 {
+            let small_weight = weights.drain(visible..).sum();
+            weights.push(small_weight);
+            entries.drain(visible..).cloned().collect()
+        } else {
+            Vec::new()
+        };
+
+        let tiles = squarify(&weights, bounds);
+        for (entry, &bounds) in entries.into_iter().zip(&tiles) {
+            let children = self.nested.get(&entry.path)
+                .filter(|_| depth + 1 < self.depth)
+                .zip(nested_bounds(bounds));
+            rects.push(ItemRect { entry: entry.clone(), bounds, depth, nested: children.is_some(), small: Vec::new() });
+            if let Some((children, inner)) = children {
+                self.layout_level(rects, children, inner, depth + 1);
             }
-            
-            remaining_entries = rest;
+        }
+        if let (Some(&bounds), false) = (tiles.last(), small.is_empty()) {
+            rects.push(ItemRect { entry: sum_entries(&small), bounds, depth, nested: false, small });
         }
     }
 
-    fn calculate_row(&self, entries: &[FileEntry], bounds: Rectangle, total_size: f32) -> (Vec<FileEntry>, Vec<FileEntry>) {
-        if entries.is_empty() {
-            return (Vec::new(), Vec::new());
+    /// Fill color of `item` under the current color scale, with `total` the
+    /// weight of everything in view.
+    fn cell_color(&self, item: &ItemRect, total: u64) -> Color {
+        let entry = &item.entry;
+        if self.diff {
+            return delta_color(entry.delta);
         }
+        match self.color_scale {
+            ColorScale::Category | ColorScale::Kind if entry.link_target.is_some() => SYMLINK_COLOR,
+            ColorScale::Category | ColorScale::Kind if entry.is_mount => MOUNT_COLOR,
+            ColorScale::Category if entry.is_dir && !category::is_cache(&entry.path) => Color::from_rgb(0.4, 0.42, 0.45),
+            ColorScale::Category if entry.is_dir => category_color(Category::Caches),
+            ColorScale::Category => category_color(category::classify(&entry.path)),
+            ColorScale::Kind if entry.is_dir => Color::from_rgb(0.2, 0.6, 0.6), // Teal for directories
+            ColorScale::Kind => Color::from_rgb(0.7, 0.2, 0.2), // Red for files
+            ColorScale::Size => {
+                let t = (self.weight(entry) as f64).ln_1p() / (total as f64).ln_1p().max(1.0);
+                size_color(t as f32)
+            }
+            ColorScale::Depth => depth_color(item.depth),
+            ColorScale::Age => {
+                let age = SystemTime::now().duration_since(entry.newest).map_or(0, |age| age.as_secs());
+                let band = age_band(age / SECONDS_PER_DAY, &self.age_breakpoints);
+                age_color(band, self.age_breakpoints.len() + 1)
+            }
+        }
+    }
 
-        let mut row = Vec::new();
-        let mut row_size = 0.0;
-        let mut i = 0;
-
-        while i < entries.len() {
-            let size = entries[i].size as f32;
-            let new_row_size = row_size + size;
-            let aspect_ratio = bounds.width / (new_row_size / total_size * bounds.height);
+    /// The deepest item under `position`, relative to a canvas of `size`,
+    /// borrowed from the cached layout.
+    pub fn find_item_at(&self, position: Point, size: Size) -> Option<Ref<'_, ItemRect>> {
+        Ref::filter_map(self.layout(size), |items| {
+            items.iter().rev().find(|item| {
+                let bounds = item.bounds;
+                position.x >= bounds.x && position.x <= bounds.x + bounds.width &&
+                position.y >= bounds.y && position.y <= bounds.y + bounds.height
+            })
+        }).ok()
+    }
 
-            if !row.is_empty() && aspect_ratio < 1.0 {
-                break;
-            }
+    fn size_text(&self, entry: &FileEntry) -> String {
+        format!(
+            "{} MB ({} MB on disk)",
+            (entry.size / 1024 / 1024).separate_with_commas(),
+            (entry.allocated / 1024 / 1024).separate_with_commas()
+        )
+    }
 
-            row_size = new_row_size;
-            row.push(entries[i].clone());
-            i += 1;
+    /// Describes the item under `cursor`, whose position is taken relative
+    /// to the treemap's `bounds`.
+    pub fn get_tooltip(&self, cursor: mouse::Cursor, bounds: Rectangle) -> Option<String> {
+        let item = self.find_item_at(cursor.position_in(bounds)?, bounds.size())?;
+        let name = item.name();
+        let size_text = self.size_text(&item.entry);
+        let type_text = if !item.small.is_empty() {
+            "Too small to show one by one; click to list them"
+        } else if item.entry.is_excluded && item.entry.is_dir && item.entry.skipped {
+            "Excluded (not read, size unknown)"
+        } else if item.entry.is_excluded {
+            "Excluded"
+        } else if item.entry.link_target.is_some() && item.entry.skipped {
+            "Symlink (loops back, not followed)"
+        } else if item.entry.link_target.is_some() {
+            "Symlink"
+        } else if item.entry.is_mount && item.entry.skipped {
+            "Mount point (not scanned)"
+        } else if item.entry.is_mount {
+            "Mount point"
+        } else if item.entry.is_dir && item.entry.partial {
+            "Directory (partial scan)"
+        } else if item.entry.is_dir {
+            "Directory"
+        } else {
+            "File"
+        };
+        let path_text = item.entry.path.to_string_lossy();
+
+        let mut tooltip = format!("{}\nType: {}\nSize: {}", name, type_text, size_text);
+        if item.entry.excluded > 0 {
+            tooltip.push_str(&format!(
+                "\nExcluded: {} MB",
+                (item.entry.excluded / 1024 / 1024).separate_with_commas()
+            ));
+        }
+        if let Some(target) = &item.entry.link_target {
+            tooltip.push_str(&format!("\nTarget: {}", target.display()));
+        }
+        if item.entry.hardlinked > 0 {
+            tooltip.push_str(&format!(
+                "\nShared via hard links: {} MB",
+                (item.entry.hardlinked / 1024 / 1024).separate_with_commas()
+            ));
         }
+        if self.diff {
+            tooltip.push_str(&format!("\nChange: {}", delta_text(item.entry.delta)));
+        }
+        tooltip.push_str(&format!("\nPath: {}", path_text));
+        Some(tooltip)
+    }
+}
+
+/// One entry adding up `entries`, which share a parent directory, standing
+/// in for them under that directory's path.
+fn sum_entries(entries: &[FileEntry]) -> FileEntry {
+    let mut sum = entries[0].clone();
+    sum.path = sum.path.parent().map(PathBuf::from).unwrap_or_default();
+    (sum.is_dir, sum.is_mount, sum.link_target, sum.skipped, sum.is_excluded, sum.partial) =
+        (false, false, None, false, false, false);
+    for entry in &entries[1..] {
+        sum.size += entry.size;
+        sum.allocated += entry.allocated;
+        sum.hardlinked += entry.hardlinked;
+        sum.excluded += entry.excluded;
+        sum.delta += entry.delta;
+        sum.created = sum.created.max(entry.created);
+        sum.modified = sum.modified.max(entry.modified);
+        sum.newest = sum.newest.max(entry.newest);
+    }
+    sum
+}
 
-        (row, entries[i..].to_vec())
+/// The space inside a directory's rectangle left for its children once the
+/// padding and header strip are taken off, if there is enough of it.
+fn nested_bounds(bounds: Rectangle) -> Option<Rectangle> {
+    let inner = Rectangle {
+        x: bounds.x + NEST_PADDING,
+        y: bounds.y + HEADER_HEIGHT,
+        width: bounds.width - 2.0 * NEST_PADDING,
+        height: bounds.height - HEADER_HEIGHT - NEST_PADDING,
+    };
+    (inner.width >= MIN_NESTED_SIZE && inner.height >= MIN_NESTED_SIZE).then_some(inner)
+}
+
+/// `text` shortened with an ellipsis to fit in `width`, or `None` if not
+/// even a few characters of it would.
+fn fit_label(text: &str, width: f32) -> Option<String> {
+    let fits = (width / LABEL_CHAR_WIDTH).max(0.0) as usize;
+    let len = text.chars().count();
+    if len <= fits {
+        Some(text.to_string())
+    } else if fits >= 4 {
+        Some(text.chars().take(fits - 1).chain(std::iter::once('…')).collect())
+    } else {
+        None
     }
+}
 
-    pub fn find_item_at(&self, position: Point) -> Option<&ItemRect> {
-        self.rects.iter().find(|item| {
-            let bounds = item.bounds;
-            position.x >= bounds.x && position.x <= bounds.x + bounds.width &&
-            position.y >= bounds.y && position.y <= bounds.y + bounds.height
-        })
+/// Black or white, whichever reads better on `background`.
+fn label_color(background: Color) -> Color {
+    let luminance = 0.2126 * background.r + 0.7152 * background.g + 0.0722 * background.b;
+    if luminance > 0.5 {
+        Color::BLACK
+    } else {
+        Color::WHITE
     }
+}
 
-    pub fn get_tooltip(&self, cursor: mouse::Cursor) -> Option<String> {
-        if let Some(position) = cursor.position() {
-            if let Some(item) = self.find_item_at(position) {
-                let name = item.entry.path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("unknown");
-                let size_text = format!("{} MB", 
-                    (item.entry.size / 1024 / 1024).separate_with_commas()
-                );
-                let type_text = if item.entry.is_dir { "Directory" } else { "File" };
-                let path_text = item.entry.path.to_string_lossy();
-                
-                return Some(format!(
-                    "{}\nType: {}\nSize: {}\nPath: {}",
-                    name, type_text, size_text, path_text
-                ));
+/// Lays out `weights`, sorted largest first, as rectangles tiling `bounds`
+/// with areas proportional to the weights, using the squarified treemap
+/// algorithm (Bruls, Huizing and van Wijk): items are packed into strips
+/// along the shorter side of the remaining space, and a strip takes on
+/// items for as long as that doesn't worsen its least square rectangle.
+pub fn squarify(weights: &[f64], bounds: Rectangle) -> Vec<Rectangle> {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 || bounds.width <= 0.0 || bounds.height <= 0.0 {
+        return Vec::new();
+    }
+    let scale = f64::from(bounds.width) * f64::from(bounds.height) / total;
+    let areas: Vec<f64> = weights.iter().map(|weight| weight * scale).collect();
+
+    let (mut x, mut y) = (f64::from(bounds.x), f64::from(bounds.y));
+    let (mut width, mut height) = (f64::from(bounds.width), f64::from(bounds.height));
+    let mut rects = Vec::with_capacity(areas.len());
+    let mut start = 0;
+    while start < areas.len() {
+        let side = width.min(height);
+        let mut end = start + 1;
+        let mut strip = areas[start];
+        while end < areas.len()
+            && worst_ratio(&areas[start..=end], strip + areas[end], side)
+                <= worst_ratio(&areas[start..end], strip, side)
+        {
+            strip += areas[end];
+            end += 1;
+        }
+
+        // The last strip takes whatever space is left, absorbing rounding.
+        let last_strip = end == areas.len();
+        if width >= height {
+            // Column along the left edge.
+            let thickness = if last_strip { width } else { strip / height };
+            let mut offset = y;
+            for (i, area) in areas[start..end].iter().enumerate() {
+                let extent = if start + i + 1 == end { y + height - offset } else { area / thickness };
+                rects.push(rectangle(x, offset, thickness, extent));
+                offset += extent;
+            }
+            x += thickness;
+            width -= thickness;
+        } else {
+            // Row along the top edge.
+            let thickness = if last_strip { height } else { strip / width };
+            let mut offset = x;
+            for (i, area) in areas[start..end].iter().enumerate() {
+                let extent = if start + i + 1 == end { x + width - offset } else { area / thickness };
+                rects.push(rectangle(offset, y, extent, thickness));
+                offset += extent;
             }
+            y += thickness;
+            height -= thickness;
         }
-        None
+        start = end;
+    }
+    rects
+}
+
+/// The largest aspect ratio among `strip`'s items once laid out along a
+/// side of length `side`.
+fn worst_ratio(strip: &[f64], sum: f64, side: f64) -> f64 {
+    let (min, max) = strip.iter().fold((f64::MAX, 0.0f64), |(min, max), area| (min.min(*area), max.max(*area)));
+    let side = side * side;
+    let sum = sum * sum;
+    (side * max / sum).max(sum / (side * min))
+}
+
+fn rectangle(x: f64, y: f64, width: f64, height: f64) -> Rectangle {
+    Rectangle { x: x as f32, y: y as f32, width: width as f32, height: height as f32 }
+}
+
+/// A signed size change, e.g. "+12 MB" or "-3 MB".
+pub fn delta_text(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{} MB", sign, (delta.unsigned_abs() / 1024 / 1024).separate_with_commas())
+}
+
+/// The size scale's color at `t`, from 0 for the smallest to 1 for the
+/// largest.
+pub fn size_color(t: f32) -> Color {
+    blend(&SIZE_SCALE, t)
+}
+
+/// The color of age band `band` out of `bands`, 0 for the freshest.
+pub fn age_color(band: usize, bands: usize) -> Color {
+    blend(&AGE_SCALE, band as f32 / bands.saturating_sub(1).max(1) as f32)
+}
+
+/// Which of the bands split by ascending `breakpoints` an age of `days`
+/// falls in, 0 for younger than the first breakpoint.
+pub fn age_band(days: u64, breakpoints: &[u64]) -> usize {
+    breakpoints.iter().take_while(|&&breakpoint| days >= breakpoint).count()
+}
+
+/// Parses comma-separated ages in days, such as "7, 30, 365", which must be
+/// ascending.
+pub fn parse_age_breakpoints(input: &str) -> Option<Vec<u64>> {
+    let breakpoints = input.split(',')
+        .map(|days| days.trim().parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    breakpoints.windows(2).all(|pair| pair[0] < pair[1]).then_some(breakpoints)
+}
+
+/// The color at `t`, from 0 to 1, along evenly spaced `stops`.
+fn blend(stops: &[Color], t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (t as usize).min(stops.len() - 2);
+    let (from, to, t) = (stops[i], stops[i + 1], t - i as f32);
+    Color::from_rgb(
+        from.r + (to.r - from.r) * t,
+        from.g + (to.g - from.g) * t,
+        from.b + (to.b - from.b) * t,
+    )
+}
+
+/// The color cells of `category` are filled with.
+pub fn category_color(category: Category) -> Color {
+    match category {
+        Category::Video => Color::from_rgb(0.8, 0.25, 0.25),
+        Category::Audio => Color::from_rgb(0.85, 0.5, 0.2),
+        Category::Images => Color::from_rgb(0.85, 0.75, 0.25),
+        Category::Archives => Color::from_rgb(0.55, 0.4, 0.25),
+        Category::DiskImages => Color::from_rgb(0.55, 0.3, 0.7),
+        Category::Code => Color::from_rgb(0.25, 0.65, 0.35),
+        Category::Documents => Color::from_rgb(0.25, 0.5, 0.8),
+        Category::Binaries => Color::from_rgb(0.2, 0.6, 0.6),
+        Category::Caches => Color::from_rgb(0.6, 0.6, 0.6),
+        Category::Other => Color::from_rgb(0.5, 0.45, 0.55),
+    }
+}
+
+/// The color of nesting level `depth`, 0 for the outermost.
+pub fn depth_color(depth: usize) -> Color {
+    DEPTH_COLORS[depth % DEPTH_COLORS.len()]
+}
+
+/// A highlight falling off into shadow across `bounds`, drawn over a cell's
+/// color so it bulges like a cushion. `draw` lays every enclosing
+/// directory's cushion over a cell before its own, so the shading builds up
+/// one level at a time and shows the nesting.
+fn cushion(bounds: Rectangle) -> canvas::Fill {
+    canvas::gradient::Linear::new(
+        Point::new(bounds.x, bounds.y),
+        Point::new(bounds.x + bounds.width, bounds.y + bounds.height),
+    )
+    .add_stop(0.0, Color::from_rgba(1.0, 1.0, 1.0, 0.25))
+    .add_stop(0.4, Color::from_rgba(1.0, 1.0, 1.0, 0.0))
+    .add_stop(1.0, Color::from_rgba(0.0, 0.0, 0.0, 0.3))
+    .into()
+}
+
+/// Red for growth, green for shrinkage.
+fn delta_color(delta: i64) -> Color {
+    if delta > 0 {
+        Color::from_rgb(0.8, 0.3, 0.2)
+    } else {
+        Color::from_rgb(0.2, 0.6, 0.3)
     }
 }
 
@@ -146,22 +547,24 @@ impl canvas::Program<crate::Message> for TreeMap {
         let mut frame = Frame::new(renderer, bounds.size());
         let selected = crate::SELECTED_PATH.lock().unwrap().clone();
 
-        // First draw all rectangles
-        for item in &self.rects {
-            let is_selected = selected.as_ref().map_or(false, |p| p == &item.entry.path);
+        let total: u64 = self.entries.iter().map(|entry| self.weight(entry)).sum();
 
-            // Calculate base color based on size and type
-            let intensity = ((item.entry.size as f32).log10() / 10.0).min(1.0).max(0.0);
-            let base_color = if item.entry.is_dir {
-                Color::from_rgb(0.2, 0.6, 0.6) // Teal for directories
-            } else {
-                Color::from_rgb(0.7, 0.2, 0.2) // Red for files
-            };
+        // Bounds of the nested directories enclosing the current item.
+        let mut ancestors: Vec<(usize, Rectangle)> = Vec::new();
+
+        // First draw all rectangles
+        for item in self.layout(bounds.size()).iter() {
+            while ancestors.last().is_some_and(|&(depth, _)| depth >= item.depth) {
+                ancestors.pop();
+            }
+            let is_selected = item.small.is_empty() && selected.as_ref() == Some(&item.entry.path);
 
             let color = if is_selected {
                 Color::from_rgb(0.2, 0.4, 0.8) // Bright blue for selected
+            } else if !item.small.is_empty() {
+                Color::from_rgb(0.35, 0.35, 0.38) // Gray for grouped small items
             } else {
-                base_color
+                self.cell_color(item, total)
             };
 
             // Draw rectangle
@@ -170,6 +573,16 @@ impl canvas::Program<crate::Message> for TreeMap {
                 Size::new(item.bounds.width, item.bounds.height),
                 color,
             );
+            for cushion_bounds in ancestors.iter().map(|&(_, bounds)| bounds).chain([item.bounds]) {
+                frame.fill_rectangle(
+                    Point::new(item.bounds.x, item.bounds.y),
+                    Size::new(item.bounds.width, item.bounds.height),
+                    cushion(cushion_bounds),
+                );
+            }
+            if item.nested {
+                ancestors.push((item.depth, item.bounds));
+            }
 
             // Draw border using stroke
             let stroke = if is_selected {
@@ -197,68 +610,86 @@ impl canvas::Program<crate::Message> for TreeMap {
                 ),
                 stroke,
             );
+
+            let name = item.name();
+            let text_color = label_color(color);
+            if item.nested {
+                if let Some(name) = fit_label(&name, item.bounds.width - 2.0 * NEST_PADDING) {
+                    frame.fill_text(canvas::Text {
+                        content: name,
+                        position: Point::new(item.bounds.x + NEST_PADDING, item.bounds.y + 1.0),
+                        color: text_color,
+                        size: LABEL_SIZE,
+                        ..canvas::Text::default()
+                    });
+                }
+                continue;
+            }
+
+            // Name and size, as many lines of it as fit.
+            let width = item.bounds.width - 2.0 * LABEL_PADDING;
+            let size = if self.diff {
+                delta_text(item.entry.delta)
+            } else {
+                humansize::format_size(item.entry.size_in(self.size_mode), humansize::WINDOWS)
+            };
+            let lines = (item.bounds.height - 2.0 * LABEL_PADDING) / (LABEL_SIZE + 2.0);
+            let label = [name.as_ref(), size.as_str()].into_iter().take(lines.max(0.0) as usize);
+            for (i, line) in label.map_while(|line| fit_label(line, width)).enumerate() {
+                frame.fill_text(canvas::Text {
+                    content: line,
+                    position: Point::new(
+                        item.bounds.x + LABEL_PADDING,
+                        item.bounds.y + LABEL_PADDING + (LABEL_SIZE + 2.0) * i as f32,
+                    ),
+                    color: text_color,
+                    size: LABEL_SIZE,
+                    ..canvas::Text::default()
+                });
+            }
         }
 
         // Then draw tooltip if mouse is over any item
-        if let Some(cursor_position) = cursor.position() {
-            // Only show tooltip if cursor is within treemap bounds
-            if bounds.contains(cursor_position) {
-                // Convert cursor position to be relative to treemap bounds
-                let relative_position = Point::new(
-                    cursor_position.x - bounds.x,
-                    cursor_position.y - bounds.y
-                );
+        if let (Some(tooltip_text), Some(cursor_position)) =
+            (self.get_tooltip(cursor, bounds), cursor.position_in(bounds))
+        {
+            let padding = 5.0;
+            let line_height = 16.0;
+            let line_count = tooltip_text.lines().count() as f32;
+            let longest_line = tooltip_text.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+            let tooltip_height = line_height * line_count + padding * 2.0;
+            let tooltip_width = (longest_line as f32 * 7.5 + padding * 2.0).min(bounds.width);
+
+            let mut tooltip_x = cursor_position.x + 10.0;
+            let mut tooltip_y = cursor_position.y + 10.0;
+
+            // Adjust position to keep tooltip within bounds
+            if tooltip_x + tooltip_width > bounds.width {
+                tooltip_x = (cursor_position.x - tooltip_width - 10.0).max(0.0);
+            }
+            if tooltip_y + tooltip_height > bounds.height {
+                tooltip_y = (cursor_position.y - tooltip_height - 10.0).max(0.0);
+            }
 
-                if let Some(item) = self.find_item_at(relative_position) {
-                    let name = item.entry.path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("unknown");
-                    let size_text = format!("{} MB", 
-                        (item.entry.size / 1024 / 1024).separate_with_commas()
-                    );
-                    let type_text = if item.entry.is_dir { "Directory" } else { "File" };
-
-                    // Draw tooltip background
-                    let tooltip_text = format!("{}\n{}\n{}", name, type_text, size_text);
-                    
-                    let padding = 5.0;
-                    let line_height = 16.0;
-                    let tooltip_height = line_height * 3.0 + padding * 2.0;
-                    let tooltip_width = 200.0;
-
-                    let mut tooltip_x = cursor_position.x + 10.0;
-                    let mut tooltip_y = cursor_position.y + 10.0;
-
-                    // Adjust position to keep tooltip within bounds
-                    if tooltip_x + tooltip_width > bounds.width + bounds.x {
-                        tooltip_x = cursor_position.x - tooltip_width - 10.0;
-                    }
-                    if tooltip_y + tooltip_height > bounds.height + bounds.y {
-                        tooltip_y = cursor_position.y - tooltip_height - 10.0;
-                    }
+            // Draw tooltip background
+            frame.fill_rectangle(
+                Point::new(tooltip_x, tooltip_y),
+                Size::new(tooltip_width, tooltip_height),
+                Color::from_rgba(0.0, 0.0, 0.0, 0.8),
+            );
 
-                    // Draw tooltip background
-                    frame.fill_rectangle(
-                        Point::new(tooltip_x, tooltip_y),
-                        Size::new(tooltip_width, tooltip_height),
-                        Color::from_rgba(0.0, 0.0, 0.0, 0.8),
-                    );
-
-                    // Draw tooltip text
-                    let lines = tooltip_text.lines();
-                    for (i, line) in lines.enumerate() {
-                        frame.fill_text(canvas::Text {
-                            content: line.to_string(),
-                            position: Point::new(
-                                tooltip_x + padding,
-                                tooltip_y + padding + line_height * i as f32
-                            ),
-                            color: Color::WHITE,
-                            size: 14.0,
-                            ..canvas::Text::default()
-                        });
-                    }
-                }
+            // Draw tooltip text
+            for (i, line) in tooltip_text.lines().enumerate() {
+                frame.fill_text(canvas::Text {
+                    content: line.to_string(),
+                    position: Point::new(
+                        tooltip_x + padding,
+                        tooltip_y + padding + line_height * i as f32
+                    ),
+                    color: Color::WHITE,
+                    size: 14.0,
+                    ..canvas::Text::default()
+                });
             }
         }
 
@@ -271,14 +702,9 @@ impl canvas::Program<crate::Message> for TreeMap {
         bounds: Rectangle,
         cursor: mouse::Cursor,
     ) -> mouse::Interaction {
-        if let Some(position) = cursor.position() {
-            if bounds.contains(position) && self.find_item_at(position).is_some() {
-                mouse::Interaction::Pointer
-            } else {
-                mouse::Interaction::default()
-            }
-        } else {
-            mouse::Interaction::default()
+        match cursor.position_in(bounds) {
+            Some(position) if self.find_item_at(position, bounds.size()).is_some() => mouse::Interaction::Pointer,
+            _ => mouse::Interaction::default(),
         }
     }
 
@@ -299,7 +725,13 @@ impl canvas::Program<crate::Message> for TreeMap {
                             cursor_position.y - bounds.y
                         );
 
-                        if let Some(item) = self.find_item_at(relative_position) {
+                        if let Some(item) = self.find_item_at(relative_position, bounds.size()) {
+                            if !item.small.is_empty() {
+                                return (
+                                    canvas::event::Status::Captured,
+                                    Some(crate::Message::ShowSmallItems(item.small.clone())),
+                                );
+                            }
                             println!("TreeMap: Selected item: {:?}", item.entry.path);
                             return (
                                 canvas::event::Status::Captured,
@@ -314,3 +746,183 @@ impl canvas::Program<crate::Message> for TreeMap {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(rect: &Rectangle) -> f64 {
+        f64::from(rect.width) * f64::from(rect.height)
+    }
+
+    fn overlap(a: &Rectangle, b: &Rectangle) -> f64 {
+        let width = (a.x + a.width).min(b.x + b.width) - a.x.max(b.x);
+        let height = (a.y + a.height).min(b.y + b.height) - a.y.max(b.y);
+        f64::from(width.max(0.0)) * f64::from(height.max(0.0))
+    }
+
+    #[test]
+    fn squarify_tiles_bounds_proportionally() {
+        let bounds = Rectangle { x: 10.0, y: 20.0, width: 600.0, height: 400.0 };
+        let weights = [6.0, 6.0, 4.0, 3.0, 2.0, 2.0, 1.0, 0.01];
+        let rects = squarify(&weights, bounds);
+        assert_eq!(rects.len(), weights.len());
+
+        let total: f64 = weights.iter().sum();
+        let bounds_area = area(&bounds);
+        for (rect, weight) in rects.iter().zip(weights) {
+            let expected = weight / total * bounds_area;
+            assert!((area(rect) - expected).abs() < expected * 1e-3 + 0.01, "{:?} for {}", rect, weight);
+            assert!(rect.x >= bounds.x - 1e-3 && rect.y >= bounds.y - 1e-3);
+            assert!(rect.x + rect.width <= bounds.x + bounds.width + 1e-3);
+            assert!(rect.y + rect.height <= bounds.y + bounds.height + 1e-3);
+        }
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                assert!(overlap(a, b) < 1e-2, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        let covered: f64 = rects.iter().map(area).sum();
+        assert!((covered - bounds_area).abs() < 1e-2 * bounds_area / 100.0);
+    }
+
+    fn entry(path: &str, size: u64, is_dir: bool) -> FileEntry {
+        FileEntry {
+            path: PathBuf::from(path),
+            size,
+            allocated: size,
+            hardlinked: 0,
+            created: std::time::UNIX_EPOCH,
+            modified: std::time::UNIX_EPOCH,
+            newest: std::time::UNIX_EPOCH,
+            is_dir,
+            is_mount: false,
+            link_target: None,
+            skipped: false,
+            is_excluded: false,
+            excluded: 0,
+            partial: false,
+            delta: 0,
+        }
+    }
+
+    #[test]
+    fn links_and_mounts_keep_their_colors_by_category() {
+        let treemap = TreeMap::new();
+        let cell = |entry| ItemRect { entry, bounds: Rectangle::default(), depth: 0, nested: false, small: Vec::new() };
+        let mut link = entry("/r/movie.mp4", 10, false);
+        link.link_target = Some(PathBuf::from("/elsewhere"));
+        let mut mount = entry("/r/Volumes", 10, true);
+        mount.is_mount = true;
+
+        assert_eq!(treemap.color_scale, ColorScale::Category);
+        assert_eq!(treemap.cell_color(&cell(link), 20), SYMLINK_COLOR);
+        assert_eq!(treemap.cell_color(&cell(mount), 20), MOUNT_COLOR);
+        let movie = cell(entry("/r/movie.mp4", 10, false));
+        assert_eq!(treemap.cell_color(&movie, 20), category_color(Category::Video));
+    }
+
+    #[test]
+    fn nested_layout_finds_the_deepest_item() {
+        let mut treemap = TreeMap::new();
+        treemap.entries = vec![entry("/r/big", 900, true), entry("/r/small.bin", 100, false)];
+        treemap.nested.insert(PathBuf::from("/r/big"), vec![entry("/r/big/inner", 900, true)]);
+        treemap.nested.insert(PathBuf::from("/r/big/inner"), vec![entry("/r/big/inner/deep.bin", 900, false)]);
+        let size = Size::new(400.0, 300.0);
+
+        treemap.depth = 3;
+        assert_eq!(treemap.layout(size).len(), 4);
+        let big = treemap.layout(size)[0].clone();
+        assert!(big.nested);
+        let center = Point::new(big.bounds.x + big.bounds.width / 2.0, big.bounds.y + big.bounds.height / 2.0);
+        let hit = treemap.find_item_at(center, size).unwrap();
+        assert_eq!(hit.entry.path, PathBuf::from("/r/big/inner/deep.bin"));
+        assert_eq!(hit.depth, 2);
+        // Hits borrow the layout, so let go before it is laid out again.
+        drop(hit);
+        // The header strip still belongs to the directory itself.
+        let header = Point::new(big.bounds.x + big.bounds.width / 2.0, big.bounds.y + 2.0);
+        assert_eq!(treemap.find_item_at(header, size).unwrap().entry.path, PathBuf::from("/r/big"));
+
+        // A resized canvas is laid out afresh to fill it.
+        let wide = Size::new(800.0, 300.0);
+        let right = treemap.layout(wide).iter().map(|item| item.bounds.x + item.bounds.width).fold(0.0, f32::max);
+        assert!((right - 800.0).abs() < 1e-3);
+
+        let mut shallow = TreeMap::new();
+        shallow.entries = treemap.entries.clone();
+        shallow.nested = treemap.nested.clone();
+        assert_eq!(shallow.layout(size).len(), 2);
+        assert!(!shallow.layout(size)[0].nested);
+    }
+
+    #[test]
+    fn size_scale_runs_through_its_stops() {
+        let close = |a: Color, b: Color| (a.r - b.r).abs() + (a.g - b.g).abs() + (a.b - b.b).abs() < 1e-5;
+        assert!(close(size_color(0.0), SIZE_SCALE[0]));
+        assert!(close(size_color(0.5), SIZE_SCALE[1]));
+        assert!(close(size_color(1.0), SIZE_SCALE[2]));
+        assert!(close(size_color(7.0), SIZE_SCALE[2]));
+        let quarter = size_color(0.25);
+        assert!((quarter.r - (SIZE_SCALE[0].r + SIZE_SCALE[1].r) / 2.0).abs() < 1e-5);
+        assert_eq!(depth_color(DEPTH_COLORS.len() + 1), depth_color(1));
+    }
+
+    #[test]
+    fn ages_fall_into_bands() {
+        let breakpoints = parse_age_breakpoints(" 7, 30,365").unwrap();
+        assert_eq!(breakpoints, vec![7, 30, 365]);
+        assert_eq!(age_band(0, &breakpoints), 0);
+        assert_eq!(age_band(7, &breakpoints), 1);
+        assert_eq!(age_band(200, &breakpoints), 2);
+        assert_eq!(age_band(5000, &breakpoints), 3);
+        assert_eq!(age_color(0, 4), AGE_SCALE[0]);
+        assert_eq!(age_color(0, 1), AGE_SCALE[0]);
+
+        assert_eq!(parse_age_breakpoints("30, 7"), None);
+        assert_eq!(parse_age_breakpoints("a week"), None);
+    }
+
+    #[test]
+    fn labels_are_shortened_to_fit() {
+        assert_eq!(fit_label("photos", 100.0).as_deref(), Some("photos"));
+        assert_eq!(fit_label("holiday-photos.zip", 70.0).as_deref(), Some("holiday-p…"));
+        assert_eq!(fit_label("holiday-photos.zip", 20.0), None);
+        assert_eq!(fit_label("", 0.0).as_deref(), Some(""));
+
+        assert_eq!(label_color(Color::from_rgb(0.9, 0.9, 0.6)), Color::BLACK);
+        assert_eq!(label_color(Color::from_rgb(0.2, 0.4, 0.8)), Color::WHITE);
+    }
+
+    #[test]
+    fn tiny_entries_share_a_cell() {
+        let mut treemap = TreeMap::new();
+        treemap.entries = vec![entry("/r/big.bin", 1_000_000, false)];
+        treemap.entries.extend((0..500).map(|i| entry(&format!("/r/tiny{}", i), 10, false)));
+        let layout = treemap.layout(Size::new(400.0, 300.0));
+        assert_eq!(layout.len(), 2);
+        let group = &layout[1];
+        assert_eq!(group.small.len(), 500);
+        assert_eq!(group.entry.size, 5000);
+        assert_eq!(group.entry.path, PathBuf::from("/r"));
+        assert_eq!(group.name(), "500 smaller items");
+        assert!(f64::from(group.bounds.width) * f64::from(group.bounds.height) > 0.0);
+    }
+
+    #[test]
+    fn squarify_prefers_square_cells() {
+        let rects = squarify(&[1.0; 4], Rectangle { x: 0.0, y: 0.0, width: 100.0, height: 100.0 });
+        for rect in &rects {
+            assert!((rect.width - 50.0).abs() < 1e-3 && (rect.height - 50.0).abs() < 1e-3, "{:?}", rect);
+        }
+
+        // The classic example from the paper: no cell worse than 3:1.
+        let weights = [6.0, 6.0, 4.0, 3.0, 2.0, 2.0, 1.0];
+        for rect in squarify(&weights, Rectangle { x: 0.0, y: 0.0, width: 6.0, height: 4.0 }) {
+            assert!(rect.width.max(rect.height) / rect.width.min(rect.height) <= 3.0, "{:?}", rect);
+        }
+
+        assert!(squarify(&[], Rectangle { x: 0.0, y: 0.0, width: 10.0, height: 10.0 }).is_empty());
+        assert!(squarify(&[1.0], Rectangle { x: 0.0, y: 0.0, width: 0.0, height: 10.0 }).is_empty());
+    }
+}